@@ -66,7 +66,7 @@ impl Saver {
     }
 
     pub fn maybe_save(&mut self, system: &System) {
-        if self.timer.elapsed().as_secs() > 0 {
+        if system.has_battery() && self.timer.elapsed().as_secs() > 0 {
             self.timer = Instant::now();
             let mut f = File::create(&self.path).unwrap();
             f.write_all(system.read_cart_sram()).unwrap();
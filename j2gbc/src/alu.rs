@@ -424,6 +424,57 @@ fn test_sbc() {
     assert!(f.get_subtract());
 }
 
+#[test]
+fn test_carry_in_flips_halfcarry() {
+    let (v, f) = adc(0x0E, 0x01, false);
+    assert_eq!(v, 0x0F);
+    assert!(!f.get_halfcarry());
+    let (v, f) = adc(0x0E, 0x01, true);
+    assert_eq!(v, 0x10);
+    assert!(f.get_halfcarry());
+
+    let (v, f) = adc(0xFE, 0x01, true);
+    assert_eq!(v, 0x00);
+    assert!(f.get_zero());
+    assert!(f.get_carry());
+
+    let (v, f) = sbc(0x10, 0x00, false);
+    assert_eq!(v, 0x10);
+    assert!(!f.get_halfcarry());
+    let (v, f) = sbc(0x10, 0x00, true);
+    assert_eq!(v, 0x0F);
+    assert!(f.get_halfcarry());
+
+    let (v, f) = sbc(0x00, 0x00, true);
+    assert_eq!(v, 0xFF);
+    assert!(f.get_carry());
+}
+
+#[test]
+fn test_sub_sbc_borrows() {
+    for left in 0..=0xFF_u8 {
+        for right in 0..=0xFF_u8 {
+            for &carry in &[false, true] {
+                let c = i32::from(carry);
+                let (l, r) = (i32::from(left), i32::from(right));
+                let result = l - r - c;
+
+                let (v, f) = sbc(left, right, carry);
+                assert_eq!(v, result as u8);
+                assert_eq!(f.get_zero(), result as u8 == 0);
+                assert!(f.get_subtract());
+                assert_eq!(f.get_halfcarry(), (l & 0x0F) - (r & 0x0F) - c < 0);
+                assert_eq!(f.get_carry(), result < 0);
+
+                if !carry {
+                    let (sub_v, sub_f) = sub(left, right);
+                    assert_eq!((sub_v, sub_f.0), (v, f.0));
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_and() {
     let (v, f) = and(0x5A, 0x3F);
@@ -679,6 +730,45 @@ fn test_rr() {
     assert!(!f.get_subtract());
 }
 
+#[test]
+fn test_rlc() {
+    let (v, f) = rlc(0x85, Flags(0));
+    assert_eq!(v, 0x0B);
+    assert!(!f.get_zero());
+    assert!(!f.get_halfcarry());
+    assert!(f.get_carry());
+    assert!(!f.get_subtract());
+
+    // The old carry doesn't feed back in
+    let mut f = Flags(0).subtract().halfcarry();
+    f.set_carry(true);
+    let (v, f) = rlc(0x00, f);
+    assert_eq!(v, 0x00);
+    assert!(f.get_zero());
+    assert!(!f.get_halfcarry());
+    assert!(!f.get_carry());
+    assert!(!f.get_subtract());
+}
+
+#[test]
+fn test_rrc() {
+    let (v, f) = rrc(0x01, Flags(0));
+    assert_eq!(v, 0x80);
+    assert!(!f.get_zero());
+    assert!(!f.get_halfcarry());
+    assert!(f.get_carry());
+    assert!(!f.get_subtract());
+
+    let mut f = Flags(0).subtract().halfcarry();
+    f.set_carry(true);
+    let (v, f) = rrc(0x00, f);
+    assert_eq!(v, 0x00);
+    assert!(f.get_zero());
+    assert!(!f.get_halfcarry());
+    assert!(!f.get_carry());
+    assert!(!f.get_subtract());
+}
+
 #[test]
 fn test_daa() {
     let (v, f) = add(0x45, 0x38);
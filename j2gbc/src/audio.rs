@@ -1,14 +1,16 @@
-use log::error;
-
-use super::mem::{Address, MemDevice, Ram, RNG_SND_WAV_RAM};
+use super::mem::{Address, MemDevice, Ram, REG_PCM12, REG_PCM34, RNG_SND_WAV_RAM};
 use crate::error::ExecutionError;
 
+mod buffer;
 mod mixer;
 mod noise;
 mod square;
 mod synth;
 mod wave;
 
+pub use self::buffer::{AudioBuffer, UnderrunPolicy};
+pub use self::square::SquareChannel;
+
 const REG_NR10: Address = Address(0xFF10);
 const REG_NR11: Address = Address(0xFF11);
 const REG_NR12: Address = Address(0xFF12);
@@ -54,10 +56,19 @@ pub struct Audio {
     nr50: u8,
     nr51: u8,
     nr52: u8,
+    cgb_mode: bool,
 
     pub synth: synth::Synth,
 }
 
+// What the sink hears while the system runs faster than real time
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TurboAudio {
+    Mute,
+    // Drop samples so the sink still receives them at its own rate
+    Resample,
+}
+
 pub trait AudioSink {
     fn emit_sample(&mut self, sample: (f32, f32));
     fn emit_raw_chans(&mut self, _chans: [f32; 4]) {}
@@ -77,7 +88,7 @@ impl AudioSink for NullSink {
 }
 
 impl Audio {
-    pub fn new(sink: Box<dyn AudioSink + Send>) -> Audio {
+    pub fn new(sink: Box<dyn AudioSink + Send>, cgb_mode: bool) -> Audio {
         Audio {
             wav: Ram::new(RNG_SND_WAV_RAM.len()),
             nr10: 0,
@@ -101,10 +112,34 @@ impl Audio {
             nr50: 0,
             nr51: 0,
             nr52: 0,
+            cgb_mode,
 
             synth: synth::Synth::new(sink),
         }
     }
+
+    // Clears the channel registers and state but keeps NR50-NR52 and wave RAM
+    pub fn reset_channels(&mut self) {
+        self.nr10 = 0;
+        self.nr11 = 0;
+        self.nr12 = 0;
+        self.nr13 = 0;
+        self.nr14 = 0;
+        self.nr21 = 0;
+        self.nr22 = 0;
+        self.nr23 = 0;
+        self.nr24 = 0;
+        self.nr30 = 0;
+        self.nr31 = 0;
+        self.nr32 = 0;
+        self.nr33 = 0;
+        self.nr34 = 0;
+        self.nr41 = 0;
+        self.nr42 = 0;
+        self.nr43 = 0;
+        self.nr44 = 0;
+        self.synth.reset_channels();
+    }
 }
 
 impl MemDevice for Audio {
@@ -130,6 +165,9 @@ impl MemDevice for Audio {
                 REG_NR42 => Ok(self.nr42),
                 REG_NR43 => Ok(self.nr43),
                 REG_NR44 => Ok(self.nr44),
+                REG_PCM12 | REG_PCM34 if !self.cgb_mode => Ok(0),
+                REG_PCM12 => Ok(self.synth.pcm_amplitudes().0),
+                REG_PCM34 => Ok(self.synth.pcm_amplitudes().1),
                 REG_NR50 => Ok(self.nr50),
                 REG_NR51 => Ok(self.nr51),
                 REG_NR52 => {
@@ -149,7 +187,7 @@ impl MemDevice for Audio {
                     Ok(v)
                 }
                 _ => {
-                    error!("Unimplemented sound register {:?}", a);
+                    error!(Audio, "Unimplemented sound register {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
@@ -331,11 +369,14 @@ impl MemDevice for Audio {
                     Ok(())
                 }
                 REG_NR52 => {
+                    if self.nr52 & 0b1000_0000 == 0 && v & 0b1000_0000 != 0 {
+                        self.synth.power_on();
+                    }
                     self.nr52 = v;
                     Ok(())
                 }
                 _ => {
-                    error!("Unimplemented sound register {:?}", a);
+                    error!(Audio, "Unimplemented sound register {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
@@ -347,6 +388,40 @@ fn bits_to_sample(b: u8) -> f32 {
     (f32::from(b) - 8.) / 8.
 }
 
+#[test]
+fn test_pcm_registers_mapped() {
+    let audio = Audio::new(Box::new(NullSink), false);
+    assert_eq!(audio.read(REG_PCM12).unwrap(), 0);
+    assert_eq!(audio.read(REG_PCM34).unwrap(), 0);
+
+    let audio = Audio::new(Box::new(NullSink), true);
+    assert_eq!(audio.read(REG_PCM34).unwrap(), 0);
+}
+
+#[test]
+fn test_power_on_resets_frame_sequencer() {
+    let mut audio = Audio::new(Box::new(NullSink), false);
+    audio.write(REG_NR52, 0b1000_0000).unwrap();
+    audio.synth.pump_cycle(10_000);
+
+    audio.write(REG_NR52, 0).unwrap();
+    audio.write(REG_NR52, 0b1000_0000).unwrap();
+    let powered_on = 12_345;
+    audio.synth.pump_cycle(powered_on);
+
+    // One tick of length left, with the counter enabled
+    audio.write(REG_NR11, 63).unwrap();
+    audio.write(REG_NR14, 0b1100_0000).unwrap();
+    assert!(audio.synth.chan1.is_active());
+
+    let mut cycle = powered_on;
+    while audio.synth.chan1.is_active() {
+        cycle += 4;
+        audio.synth.pump_cycle(cycle);
+    }
+    assert_eq!(cycle - powered_on, synth::FRAME_SEQUENCER_STEP);
+}
+
 #[test]
 fn test_bits_to_sample() {
     assert_eq!(bits_to_sample(0), -1.);
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::AudioSink;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnderrunPolicy {
+    // Pad with silence
+    Silence,
+    // Hold the last sample produced
+    Repeat,
+    // Spread whatever samples are available across the whole request
+    Stretch,
+}
+
+// A queue between the emulator and an audio callback, for front-ends that pull samples
+pub struct AudioBuffer {
+    samples: VecDeque<(f32, f32)>,
+    rate: u64,
+    policy: UnderrunPolicy,
+    last: (f32, f32),
+}
+
+impl AudioBuffer {
+    pub fn new(rate: u64) -> AudioBuffer {
+        AudioBuffer {
+            samples: VecDeque::new(),
+            rate,
+            policy: UnderrunPolicy::Silence,
+            last: (0., 0.),
+        }
+    }
+
+    pub fn set_underrun_policy(&mut self, policy: UnderrunPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn push(&mut self, sample: (f32, f32)) {
+        self.samples.push_back(sample);
+    }
+
+    // Fills all of out, returning how many real samples were used
+    pub fn drain(&mut self, out: &mut [(f32, f32)]) -> usize {
+        let available = self.samples.len().min(out.len());
+
+        if available < out.len() && available > 0 && self.policy == UnderrunPolicy::Stretch {
+            let taken: Vec<_> = self.samples.drain(..available).collect();
+            let len = out.len();
+            for (i, o) in out.iter_mut().enumerate() {
+                *o = taken[i * available / len];
+            }
+            self.last = taken[available - 1];
+            return available;
+        }
+
+        for (o, s) in out.iter_mut().zip(self.samples.drain(..available)) {
+            *o = s;
+            self.last = s;
+        }
+
+        let filler = match self.policy {
+            UnderrunPolicy::Silence => (0., 0.),
+            UnderrunPolicy::Repeat | UnderrunPolicy::Stretch => self.last,
+        };
+        for o in out[available..].iter_mut() {
+            *o = filler;
+        }
+
+        available
+    }
+}
+
+impl AudioSink for AudioBuffer {
+    fn emit_sample(&mut self, sample: (f32, f32)) {
+        self.push(sample);
+    }
+
+    fn sample_rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+// Lets a front-end keep a handle to drain from while the emulator owns the sink
+impl AudioSink for Arc<Mutex<AudioBuffer>> {
+    fn emit_sample(&mut self, sample: (f32, f32)) {
+        self.lock().unwrap().push(sample);
+    }
+
+    fn sample_rate(&self) -> u64 {
+        self.lock().unwrap().rate
+    }
+}
+
+#[test]
+fn test_underrun_policies() {
+    let drain_with = |policy| {
+        let mut buffer = AudioBuffer::new(48_000);
+        buffer.set_underrun_policy(policy);
+        buffer.push((0.25, 0.25));
+        buffer.push((0.5, -0.5));
+
+        let mut out = [(1., 1.); 4];
+        assert_eq!(buffer.drain(&mut out), 2);
+        assert!(buffer.is_empty());
+        out
+    };
+
+    assert_eq!(
+        drain_with(UnderrunPolicy::Silence),
+        [(0.25, 0.25), (0.5, -0.5), (0., 0.), (0., 0.)]
+    );
+    assert_eq!(
+        drain_with(UnderrunPolicy::Repeat),
+        [(0.25, 0.25), (0.5, -0.5), (0.5, -0.5), (0.5, -0.5)]
+    );
+    assert_eq!(
+        drain_with(UnderrunPolicy::Stretch),
+        [(0.25, 0.25), (0.25, 0.25), (0.5, -0.5), (0.5, -0.5)]
+    );
+}
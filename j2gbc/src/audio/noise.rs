@@ -8,6 +8,8 @@ pub struct NoiseChannel {
 
     len: u8,
     pub use_len: bool,
+    // Set by a trigger; a channel that has never been triggered stays silent
+    triggered: bool,
 
     next_lfsr_shift_cycle: u64,
     last_cpu_cycle: u64,
@@ -36,6 +38,7 @@ impl NoiseChannel {
 
             len: 0,
             use_len: false,
+            triggered: false,
 
             next_lfsr_shift_cycle: 0,
             last_cpu_cycle: 0,
@@ -116,10 +119,11 @@ impl NoiseChannel {
     }
 
     pub fn is_active(&self) -> bool {
-        !self.use_len || self.len > 0
+        self.triggered && (!self.use_len || self.len > 0)
     }
 
     pub fn reset(&mut self) {
+        self.triggered = true;
         if self.len == 0 {
             self.len = 64;
         }
@@ -5,6 +5,8 @@ pub struct SquareChannel {
     duty_cycle: u8,
     use_len: bool,
     len: u8,
+    // Set by a trigger; a channel that has never been triggered stays silent
+    triggered: bool,
     last_cpu_cycle: u64,
     duty_cycle_step: usize,
     duty_cycle_step_timer: Timer,
@@ -16,6 +18,8 @@ pub struct SquareChannel {
     vol_counter: Clock,
 
     frequency: u64,
+    // Only channel 1 has the frequency sweep unit
+    has_sweep: bool,
     frequency_shift: u8,
     frequency_increment: bool,
     frequency_sweep_counter: Clock,
@@ -36,6 +40,10 @@ impl Default for SquareChannel {
 
 impl SquareChannel {
     pub fn new() -> SquareChannel {
+        SquareChannel::with_sweep(true)
+    }
+
+    pub fn with_sweep(has_sweep: bool) -> SquareChannel {
         SquareChannel {
             period: 0,
             duty_cycle: 0,
@@ -44,6 +52,7 @@ impl SquareChannel {
             duty_cycle_step_timer_offset: 0,
             use_len: false,
             len: 0,
+            triggered: false,
             last_cpu_cycle: 0,
 
             vol: 0,
@@ -52,6 +61,7 @@ impl SquareChannel {
             vol_counter: Clock::new(0),
 
             frequency: 0,
+            has_sweep,
             frequency_shift: 0,
             frequency_increment: false,
             frequency_sweep_counter: Clock::new(0),
@@ -59,6 +69,7 @@ impl SquareChannel {
     }
 
     pub fn reset(&mut self) {
+        self.triggered = true;
         self.frequency_sweep_counter.reset();
         self.vol_counter.reset();
         if self.len == 0 {
@@ -86,13 +97,16 @@ impl SquareChannel {
         freqeuncy_shift: u8,
         freqeuncy_increment: bool,
     ) {
+        if !self.has_sweep {
+            return;
+        }
         self.frequency_sweep_counter = Clock::new(u64::from(freqeuncy_period));
         self.frequency_shift = freqeuncy_shift;
         self.frequency_increment = freqeuncy_increment;
     }
 
     pub fn freq_sweep_update(&mut self) {
-        if self.frequency_sweep_counter.period() == 0 {
+        if !self.has_sweep || self.frequency_sweep_counter.period() == 0 {
             return;
         }
 
@@ -175,6 +189,48 @@ impl SquareChannel {
     }
 
     pub fn is_active(&self) -> bool {
-        !self.use_len || self.len > 0
+        self.triggered && (!self.use_len || self.len > 0)
+    }
+
+    pub fn current_phase(&self) -> usize {
+        self.duty_cycle_step
+    }
+
+    pub fn duty_pattern(&self) -> [f32; 8] {
+        DUTY_VALUES[self.duty_cycle as usize]
     }
 }
+
+#[test]
+fn test_current_phase() {
+    let mut chan = SquareChannel::new();
+    chan.set_volume(15);
+    chan.set_duty_cycle(2);
+    chan.set_frequency_from_bits(0b111, 0xF0); // 64 cycles per step
+    chan.reset();
+
+    chan.sample(0);
+    let start = chan.current_phase();
+    chan.sample(64 * 3);
+    assert_eq!(chan.current_phase(), (start + 3) % 8);
+    chan.sample(64 * 8);
+    assert_eq!(chan.current_phase(), start);
+    assert_eq!(chan.duty_pattern(), DUTY_VALUES[2]);
+}
+
+#[test]
+fn test_no_sweep_without_sweep_unit() {
+    let mut chan1 = SquareChannel::with_sweep(true);
+    let mut chan2 = SquareChannel::with_sweep(false);
+    for chan in [&mut chan1, &mut chan2].iter_mut() {
+        chan.set_frequency_from_bits(0b011, 0x00);
+        chan.set_freqeuncy_sweepers(1, 1, true);
+        for _ in 0..4 {
+            chan.freq_sweep_update();
+        }
+    }
+
+    assert_ne!(chan1.frequency, 0x300);
+    assert_eq!(chan2.frequency, 0x300);
+    assert_eq!(chan2.period, 4 * (2048 - 0x300));
+}
@@ -2,9 +2,14 @@ use j2ds::{next_timer_event, Timer, TimerEvent};
 
 use super::{
     mixer::Mixer, noise::NoiseChannel, square::SquareChannel, wave::WaveChannel, AudioSink,
+    TurboAudio,
 };
 use crate::cpu::CLOCK_RATE;
 
+// The frame sequencer steps at 512Hz; length is clocked on even steps, sweep on steps 2 and 6
+// and the envelope on step 7
+pub const FRAME_SEQUENCER_STEP: u64 = CLOCK_RATE / 512;
+
 pub struct Synth {
     sink: Box<dyn AudioSink + Send>,
 
@@ -12,6 +17,8 @@ pub struct Synth {
     len_clock: Timer,
     env_clock: Timer,
     freq_clock: Timer,
+    // The sequencer clocks count from the cycle of its first step after power on
+    frame_sequencer_start: u64,
 
     pub mixer: Mixer,
 
@@ -19,6 +26,16 @@ pub struct Synth {
     pub chan2: SquareChannel,
     pub chan3: WaveChannel,
     pub chan4: NoiseChannel,
+
+    capture_samples: bool,
+    captured_samples: Vec<(f32, f32)>,
+    last_samples: [f32; 4],
+    power_on_pending: bool,
+
+    // Only one in every `speedup` samples is passed on to the sink
+    speedup: u64,
+    turbo_audio: TurboAudio,
+    sample_count: u64,
 }
 
 impl Synth {
@@ -28,28 +45,94 @@ impl Synth {
             len_clock: Timer::new(CLOCK_RATE / 256, 0, 0),
             env_clock: Timer::new(CLOCK_RATE / 64, 0, 0),
             freq_clock: Timer::new(CLOCK_RATE / 128, 0, 0),
+            frame_sequencer_start: 0,
 
             sink,
 
             mixer: Mixer::new(),
 
             chan1: SquareChannel::new(),
-            chan2: SquareChannel::new(),
+            chan2: SquareChannel::with_sweep(false),
             chan3: WaveChannel::new(),
             chan4: NoiseChannel::new(),
+
+            capture_samples: false,
+            captured_samples: Vec::new(),
+            last_samples: [0.; 4],
+            power_on_pending: false,
+
+            speedup: 1,
+            turbo_audio: TurboAudio::Resample,
+            sample_count: 0,
         }
     }
 
+    // The sequencer restarts at step 0 on the next pump
+    pub fn power_on(&mut self) {
+        self.power_on_pending = true;
+    }
+
+    // Every channel is silent until it's triggered again; the mixer is left alone
+    pub fn reset_channels(&mut self) {
+        self.chan1 = SquareChannel::new();
+        self.chan2 = SquareChannel::with_sweep(false);
+        self.chan3.power_on_reset();
+        self.chan4 = NoiseChannel::new();
+
+        self.last_samples = [0.; 4];
+        self.power_on();
+    }
+
+    fn reset_frame_sequencer(&mut self, cpu_cycle: u64) {
+        self.len_clock = Timer::new(CLOCK_RATE / 256, 0, 0);
+        self.freq_clock = Timer::new(CLOCK_RATE / 128, 2 * FRAME_SEQUENCER_STEP, 0);
+        self.env_clock = Timer::new(CLOCK_RATE / 64, 7 * FRAME_SEQUENCER_STEP, 0);
+        self.frame_sequencer_start = cpu_cycle + FRAME_SEQUENCER_STEP;
+    }
+
+    pub fn set_speedup(&mut self, speedup: u64, turbo_audio: TurboAudio) {
+        self.speedup = speedup.max(1);
+        self.turbo_audio = turbo_audio;
+    }
+
+    pub fn start_sample_capture(&mut self) {
+        self.captured_samples.clear();
+        self.capture_samples = true;
+    }
+
+    pub fn stop_sample_capture(&mut self) -> &[(f32, f32)] {
+        self.capture_samples = false;
+        &self.captured_samples
+    }
+
+    // Digital amplitudes of each channel packed as PCM12 and PCM34. A silent wave channel
+    // outputs exactly 0, which is reported as 0 rather than its midpoint.
+    pub fn pcm_amplitudes(&self) -> (u8, u8) {
+        let pulse = |s: f32| (s.max(0.) * 15.).round() as u8;
+        let wave = |s: f32| {
+            if s == 0. {
+                0
+            } else {
+                (s * 8. + 8.).round().clamp(0., 15.) as u8
+            }
+        };
+
+        let [c1, c2, c3, c4] = self.last_samples;
+        (pulse(c1) | pulse(c2) << 4, wave(c3) | pulse(c4) << 4)
+    }
+
     pub fn get_next_event_cycle(&self) -> u64 {
-        next_timer_event(&[
-            self.sample_clock,
-            self.len_clock,
-            self.env_clock,
-            self.freq_clock,
-        ])
+        let frame_sequencer_event = self.frame_sequencer_start
+            + next_timer_event(&[self.len_clock, self.env_clock, self.freq_clock]);
+        next_timer_event(&[self.sample_clock]).min(frame_sequencer_event)
     }
 
     pub fn pump_cycle(&mut self, cpu_cycle: u64) {
+        if self.power_on_pending {
+            self.power_on_pending = false;
+            self.reset_frame_sequencer(cpu_cycle);
+        }
+
         if self.sample_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
             let samples = [
                 self.chan1.sample(cpu_cycle),
@@ -57,25 +140,73 @@ impl Synth {
                 self.chan3.sample(cpu_cycle),
                 self.chan4.sample(cpu_cycle),
             ];
-            self.sink.emit_sample(self.mixer.mix(samples));
-            self.sink.emit_raw_chans(samples);
+            self.last_samples = samples;
+            let mixed = self.mixer.mix(samples);
+            if self.capture_samples {
+                self.captured_samples.push(mixed);
+            }
+
+            self.sample_count += 1;
+            if self.sample_count.is_multiple_of(self.speedup) {
+                if self.speedup > 1 && self.turbo_audio == TurboAudio::Mute {
+                    self.sink.emit_sample((0., 0.));
+                    self.sink.emit_raw_chans([0.; 4]);
+                } else {
+                    self.sink.emit_sample(mixed);
+                    self.sink.emit_raw_chans(samples);
+                }
+            }
         }
 
-        if self.len_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
+        // Nothing is clocked before the first step
+        let sequencer_cycle = match cpu_cycle.checked_sub(self.frame_sequencer_start) {
+            Some(sequencer_cycle) => sequencer_cycle,
+            None => return,
+        };
+
+        if self.len_clock.update(sequencer_cycle) == Some(TimerEvent::RisingEdge) {
             self.chan1.decrement_length();
             self.chan2.decrement_length();
             self.chan3.decrement_length();
             self.chan4.decrement_length();
         }
 
-        if self.env_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
+        if self.env_clock.update(sequencer_cycle) == Some(TimerEvent::RisingEdge) {
             self.chan1.volume_env_update();
             self.chan2.volume_env_update();
             self.chan4.volume_env_update();
         }
 
-        if self.freq_clock.update(cpu_cycle) == Some(TimerEvent::RisingEdge) {
+        if self.freq_clock.update(sequencer_cycle) == Some(TimerEvent::RisingEdge) {
             self.chan1.freq_sweep_update();
         }
     }
 }
+
+#[test]
+fn test_pcm_amplitudes() {
+    let mut synth = Synth::new(Box::new(super::NullSink));
+    assert_eq!(synth.pcm_amplitudes(), (0x00, 0x00));
+
+    synth.last_samples = [1., -1., 0.5, 7. / 15.];
+    assert_eq!(synth.pcm_amplitudes(), (0x0F, 0x7C));
+}
+
+#[test]
+fn test_speedup_drops_sink_samples() {
+    use std::sync::{Arc, Mutex};
+
+    let emitted = |speedup| {
+        let buffer = Arc::new(Mutex::new(super::AudioBuffer::new(CLOCK_RATE / 64)));
+        let mut synth = Synth::new(Box::new(buffer.clone()));
+        synth.set_speedup(speedup, TurboAudio::Resample);
+        for cycle in (0..64 * 400).step_by(4) {
+            synth.pump_cycle(cycle);
+        }
+        let len = buffer.lock().unwrap().len();
+        len
+    };
+
+    assert_eq!(emitted(1), 400);
+    assert_eq!(emitted(4), 100);
+}
@@ -1,9 +1,15 @@
+// When ramping, the output may only move this far per sample after a trigger
+const RAMP_STEP: f32 = 0.125;
+const RAMP_SAMPLES: u8 = 16;
+
 #[derive(Default)]
 pub struct WaveChannel {
     samples: [f32; 32],
     period: u64,
     pub use_len: bool,
     len: u8,
+    // Set by a trigger; a channel that has never been triggered stays silent
+    triggered: bool,
 
     pub enabled: bool,
 
@@ -11,6 +17,10 @@ pub struct WaveChannel {
 
     position_offset_cycle: u64,
     last_cpu_cycle: u64,
+
+    ramp_on_trigger: bool,
+    ramp_remaining: u8,
+    last_output: f32,
 }
 
 impl WaveChannel {
@@ -20,15 +30,33 @@ impl WaveChannel {
             period: 0,
             use_len: false,
             len: 0,
+            triggered: false,
 
             enabled: false,
             vol_multiplier: 0.,
 
             position_offset_cycle: 0,
             last_cpu_cycle: 0,
+
+            ramp_on_trigger: false,
+            ramp_remaining: 0,
+            last_output: 0.,
         }
     }
 
+    // Back to the power-on state, keeping the wave pattern and the ramp setting
+    pub fn power_on_reset(&mut self) {
+        *self = WaveChannel {
+            samples: self.samples,
+            ramp_on_trigger: self.ramp_on_trigger,
+            ..WaveChannel::new()
+        };
+    }
+
+    pub fn set_trigger_ramp(&mut self, ramp: bool) {
+        self.ramp_on_trigger = ramp;
+    }
+
     pub fn set_frequency_from_bits(&mut self, hi: u8, lo: u8) {
         let f = (u64::from(hi) & 0b111) << 8 | u64::from(lo);
         self.period = (2048 - f) * 2 * 32;
@@ -49,6 +77,16 @@ impl WaveChannel {
     }
 
     pub fn sample(&mut self, cpu_cycle: u64) -> f32 {
+        let mut output = self.raw_sample(cpu_cycle);
+        if self.ramp_remaining > 0 {
+            self.ramp_remaining -= 1;
+            output = self.last_output + (output - self.last_output).clamp(-RAMP_STEP, RAMP_STEP);
+        }
+        self.last_output = output;
+        output
+    }
+
+    fn raw_sample(&mut self, cpu_cycle: u64) -> f32 {
         if self.period == 0 || !self.is_active() {
             return 0.;
         }
@@ -60,13 +98,39 @@ impl WaveChannel {
     }
 
     pub fn is_active(&self) -> bool {
-        (!self.use_len || self.len > 0) && self.enabled
+        self.triggered && (!self.use_len || self.len > 0) && self.enabled
     }
 
     pub fn reset(&mut self) {
+        self.triggered = true;
         self.position_offset_cycle = self.last_cpu_cycle;
         if self.len == 0 {
             self.len = 255;
         }
+        if self.ramp_on_trigger {
+            self.ramp_remaining = RAMP_SAMPLES;
+        }
+    }
+}
+
+#[test]
+fn test_trigger_ramp() {
+    fn triggered_first_sample(ramp: bool) -> f32 {
+        let mut chan = WaveChannel::new();
+        chan.enabled = true;
+        chan.vol_multiplier = 1.;
+        chan.set_frequency_from_bits(0, 0);
+        chan.reset();
+        chan.set_trigger_ramp(ramp);
+        assert_eq!(chan.sample(0), -1.);
+
+        for i in 0..32 {
+            chan.write_sample(1., i);
+        }
+        chan.reset();
+        chan.sample(1)
     }
+
+    assert_eq!(triggered_first_sample(false), 1.);
+    assert_eq!(triggered_first_sample(true), -1. + RAMP_STEP);
 }
@@ -2,78 +2,175 @@ use std::io;
 use std::io::Read;
 
 use crate::error::ExecutionError;
-use crate::mbc::mbc0::Mbc0;
-use crate::mbc::mbc1::Mbc1;
-use crate::mbc::mbc5::Mbc5;
-use crate::mbc::Mbc;
+use crate::mbc::{self, Mbc, MbcKind};
 use crate::mem::{
-    Address, ExtendedAddress, MemDevice, RNG_INTR_TABLE, RNG_ROM_BANK0, RNG_ROM_BANK1,
+    Address, ExtendedAddress, MemDevice, RamFill, RNG_INTR_TABLE, RNG_ROM_BANK0, RNG_ROM_BANK1,
 };
 use crate::mmu_exceptions::MmuExceptions;
 
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LoadOptions {
+    pub fallback_unsupported_mbc: bool,
+    pub ram_fill: RamFill,
+    pub seed: u64,
+    // Refuse to load ROMs whose size disagrees with the header instead of warning
+    pub strict_rom_size: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoadWarning {
+    RomSizeMismatch { header: usize, actual: usize },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartHeader {
+    pub title: String,
+    pub cart_type: u8,
+    pub rom_size: Option<usize>,
+    pub ram_size: Option<usize>,
+    pub cgb_flag: u8,
+    pub destination_code: u8,
+}
+
+impl CartHeader {
+    // None when the data is too short to hold a header
+    pub fn parse(data: &[u8]) -> Option<CartHeader> {
+        if data.len() < HEADER_END {
+            return None;
+        }
+
+        Some(CartHeader {
+            title: parse_title(data),
+            cart_type: data[OFF_CART_TYPE],
+            rom_size: header_rom_size(data[OFF_CART_SIZE]),
+            ram_size: header_ram_size(data[OFF_RAM_SIZE]),
+            cgb_flag: data[OFF_CART_CGB_SUPPORTED],
+            destination_code: data[OFF_DESTINATION_CODE],
+        })
+    }
+}
+
 pub struct Cart {
     pub data: Vec<u8>,
     mbc: Box<dyn Mbc + Send>,
+    warnings: Vec<LoadWarning>,
 }
 
 const OFF_CART_NAME_START: usize = 0x134;
 const OFF_CART_NAME_END: usize = 0x142;
 const OFF_CART_CGB_SUPPORTED: usize = 0x143;
-const OFF_CART_TYPE: usize = 0x147;
+pub const OFF_CART_TYPE: usize = 0x147;
 const OFF_CART_SIZE: usize = 0x148;
-const OFF_RAM_SIZE: usize = 0x149;
+pub const OFF_RAM_SIZE: usize = 0x149;
+const OFF_DESTINATION_CODE: usize = 0x14A;
+const OFF_HEADER_CHECKSUM: usize = 0x14D;
+const HEADER_END: usize = 0x150;
+
+// Room for a copier header or trailing padding before a size mismatch is reported
+const ROM_SIZE_TOLERANCE: usize = 512;
 
 impl Cart {
-    pub fn load<R: Read>(mut r: R) -> io::Result<Cart> {
+    pub fn load<R: Read>(mut r: R, options: &LoadOptions) -> io::Result<Cart> {
         let mut data = Vec::new();
         r.read_to_end(&mut data)?;
 
-        let mbc: Box<dyn Mbc + Send> = match data[OFF_CART_TYPE] {
-            0x00 => Box::new(Mbc0::new(data.clone())),
-            0x01 | 0x02 | 0x03 => Box::new(Mbc1::new(data.clone())),
-            0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Box::new(Mbc5::new(data.clone())),
-            _ => {
-                unimplemented!("Unsupported MBC {}", data[OFF_CART_TYPE]);
+        let mut warnings = Vec::new();
+        if let Some(header) = header_rom_size(data[OFF_CART_SIZE]) {
+            let actual = data.len();
+            if actual < header || actual > header + ROM_SIZE_TOLERANCE {
+                if options.strict_rom_size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("ROM is {} bytes but the header says {}", actual, header),
+                    ));
+                }
+                warn!(
+                    Cart,
+                    "ROM is {} bytes but the header says {}", actual, header
+                );
+                warnings.push(LoadWarning::RomSizeMismatch { header, actual });
+            }
+        }
+
+        let mut type_ = data[OFF_CART_TYPE];
+        if !mbc::is_supported(type_) && options.fallback_unsupported_mbc {
+            if let Some(fallback) = fallback_mbc(type_) {
+                warn!(
+                    Cart,
+                    "Unsupported MBC {:#X}, falling back to MBC {:#X}", type_, fallback
+                );
+                type_ = fallback;
+            }
+        }
+
+        let mbc = match mbc::for_cart_type(type_, data.clone()) {
+            Some(mbc) => mbc,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported MBC {:#X}", type_),
+                ));
             }
         };
 
-        Ok(Cart { data, mbc })
+        Ok(Cart {
+            data,
+            mbc,
+            warnings,
+        })
     }
 
-    pub fn name(&self) -> String {
-        let b = &self.data.as_slice()[OFF_CART_NAME_START..OFF_CART_NAME_END];
-        let s = b
+    pub fn load_warnings(&self) -> &[LoadWarning] {
+        &self.warnings
+    }
+
+    pub fn header(&self) -> Option<CartHeader> {
+        CartHeader::parse(&self.data)
+    }
+
+    // The same check the boot ROM runs before handing over to the cart. A truncated header
+    // never passes.
+    pub fn verify_header_checksum(&self) -> bool {
+        if self.data.len() < HEADER_END {
+            return false;
+        }
+
+        let x = self.data[OFF_CART_NAME_START..OFF_HEADER_CHECKSUM]
             .iter()
-            .take_while(|n| **n != 0)
-            .cloned()
-            .collect::<Vec<u8>>();
-        String::from_utf8_lossy(&s[..]).into_owned()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        x == self.data[OFF_HEADER_CHECKSUM]
+    }
+
+    pub fn name(&self) -> String {
+        parse_title(&self.data)
     }
 
     pub fn type_(&self) -> u8 {
         self.data[OFF_CART_TYPE]
     }
 
-    pub fn rom_size(&self) -> usize {
-        32768 << self.data[OFF_CART_SIZE]
+    pub fn mbc_kind(&self) -> MbcKind {
+        self.mbc.kind()
     }
 
-    pub fn ram_size(&self) -> usize {
-        match self.data[OFF_RAM_SIZE] {
-            0 => 0,
-            1 => 2048,
-            2 => 8192,
-            3 => 32_768,
-            4 => 131_072,
-            _ => unimplemented!(),
-        }
+    // Both are None when the header uses a code that isn't understood
+    pub fn rom_size(&self) -> Option<usize> {
+        self.data
+            .get(OFF_CART_SIZE)
+            .and_then(|code| header_rom_size(*code))
+    }
+
+    pub fn ram_size(&self) -> Option<usize> {
+        self.data
+            .get(OFF_RAM_SIZE)
+            .and_then(|code| header_ram_size(*code))
     }
 
     pub fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
         if a.in_(RNG_ROM_BANK1) {
             self.mbc.map_address_into_rom(a)
         } else {
-            ExtendedAddress(u32::from(a.0))
+            self.mbc.map_address_into_rom_bank0(a)
         }
     }
 
@@ -85,6 +182,10 @@ impl Cart {
         self.mbc.set_sram(buf);
     }
 
+    pub fn has_battery(&self) -> bool {
+        self.mbc.has_battery()
+    }
+
     pub fn get_mmu_exceptions(&self) -> MmuExceptions {
         MmuExceptions::from_title(self.name().as_str())
     }
@@ -95,10 +196,50 @@ impl Cart {
     }
 }
 
+fn header_rom_size(code: u8) -> Option<usize> {
+    if code <= 8 {
+        Some(32768 << code)
+    } else {
+        None
+    }
+}
+
+pub fn header_ram_size(code: u8) -> Option<usize> {
+    match code {
+        0 => Some(0),
+        1 => Some(2048),
+        2 => Some(8192),
+        3 => Some(32_768),
+        4 => Some(131_072),
+        5 => Some(65_536),
+        _ => None,
+    }
+}
+
+fn parse_title(data: &[u8]) -> String {
+    let b = &data[OFF_CART_NAME_START..OFF_CART_NAME_END];
+    let s = b
+        .iter()
+        .take_while(|n| **n != 0)
+        .cloned()
+        .collect::<Vec<u8>>();
+    String::from_utf8_lossy(&s[..]).into_owned()
+}
+
+// Picks the implemented MBC whose register layout is closest to the requested one
+fn fallback_mbc(type_: u8) -> Option<u8> {
+    match type_ {
+        0x08 | 0x09 => Some(0x00),
+        0x0B..=0x0D | 0xFF => Some(0x03),
+        0x20 | 0x22 | 0xFC..=0xFE => Some(0x1B),
+        _ => None,
+    }
+}
+
 impl MemDevice for Cart {
     fn read(&self, a: Address) -> Result<u8, ExecutionError> {
         if a.in_(RNG_ROM_BANK0) || a.in_(RNG_INTR_TABLE) {
-            Ok(self.data[a.0 as usize])
+            Ok(self.data[self.mbc.map_address_into_rom_bank0(a).0 as usize])
         } else {
             self.mbc.read(a)
         }
@@ -108,3 +249,124 @@ impl MemDevice for Cart {
         self.mbc.write(a, v)
     }
 }
+
+#[test]
+fn test_has_battery() {
+    use std::io::Cursor;
+
+    let load = |type_, fallback_unsupported_mbc| {
+        let mut rom = vec![0; 0x8000];
+        rom[OFF_CART_TYPE] = type_;
+        let options = LoadOptions {
+            fallback_unsupported_mbc,
+            ..LoadOptions::default()
+        };
+        Cart::load(Cursor::new(rom), &options).unwrap()
+    };
+
+    assert!(!load(0x00, false).has_battery());
+    assert!(!load(0x01, false).has_battery());
+    assert!(load(0x03, false).has_battery());
+    assert!(!load(0x19, false).has_battery());
+    assert!(load(0x1B, false).has_battery());
+    assert!(load(0x10, false).has_battery());
+    assert!(!load(0x11, false).has_battery());
+    // MBC7 keeps its battery when emulated as MBC5
+    assert!(load(0x22, true).has_battery());
+    assert!(!load(0x20, true).has_battery());
+}
+
+#[test]
+fn test_rom_size_mismatch() {
+    use std::io::Cursor;
+
+    let load = |len, strict_rom_size| {
+        let mut rom = vec![0; len];
+        rom[OFF_CART_SIZE] = 1; // 64KB
+        let options = LoadOptions {
+            strict_rom_size,
+            ..LoadOptions::default()
+        };
+        Cart::load(Cursor::new(rom), &options)
+    };
+
+    assert_eq!(load(0x10000, false).unwrap().load_warnings(), &[]);
+    assert_eq!(load(0x10000 + 512, false).unwrap().load_warnings(), &[]);
+    assert_eq!(
+        load(0x8000, false).unwrap().load_warnings(),
+        &[LoadWarning::RomSizeMismatch {
+            header: 0x10000,
+            actual: 0x8000
+        }]
+    );
+    assert_eq!(
+        load(0x20000, false).unwrap().load_warnings(),
+        &[LoadWarning::RomSizeMismatch {
+            header: 0x10000,
+            actual: 0x20000
+        }]
+    );
+
+    assert!(load(0x10000, true).is_ok());
+    assert!(load(0x8000, true).is_err());
+}
+
+#[test]
+fn test_header() {
+    use std::io::Cursor;
+
+    let mut rom = vec![0; 0x10000];
+    rom[0x134..0x13C].copy_from_slice(b"POKEMON\0");
+    rom[OFF_CART_CGB_SUPPORTED] = 0x80;
+    rom[OFF_CART_TYPE] = 0x13;
+    rom[OFF_CART_SIZE] = 0x01;
+    rom[OFF_RAM_SIZE] = 0x03;
+    rom[OFF_DESTINATION_CODE] = 0x01;
+    let cart = Cart::load(Cursor::new(rom), &LoadOptions::default()).unwrap();
+
+    assert_eq!(
+        cart.header(),
+        Some(CartHeader {
+            title: "POKEMON".to_owned(),
+            cart_type: 0x13,
+            rom_size: Some(0x10000),
+            ram_size: Some(32_768),
+            cgb_flag: 0x80,
+            destination_code: 0x01,
+        })
+    );
+
+    let mut rom = vec![0; 0x8000];
+    rom[OFF_CART_SIZE] = 0x52;
+    rom[OFF_RAM_SIZE] = 0x09;
+    let header = CartHeader::parse(&rom).unwrap();
+    assert_eq!(header.rom_size, None);
+    assert_eq!(header.ram_size, None);
+
+    let cart = Cart::load(Cursor::new(rom.clone()), &LoadOptions::default()).unwrap();
+    assert_eq!(cart.rom_size(), None);
+    assert_eq!(cart.ram_size(), None);
+
+    assert_eq!(CartHeader::parse(&rom[..HEADER_END - 1]), None);
+    assert_eq!(CartHeader::parse(&[]), None);
+}
+
+#[test]
+fn test_header_checksum() {
+    use std::io::Cursor;
+
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x13A].copy_from_slice(b"TETRIS");
+    rom[0x14B] = 0x01;
+    rom[0x14C] = 0x01;
+    rom[OFF_HEADER_CHECKSUM] = 0x0A;
+    let cart = Cart::load(Cursor::new(rom.clone()), &LoadOptions::default()).unwrap();
+    assert!(cart.verify_header_checksum());
+
+    rom[0x135] ^= 0x20;
+    let mut cart = Cart::load(Cursor::new(rom), &LoadOptions::default()).unwrap();
+    assert!(!cart.verify_header_checksum());
+
+    cart.data.truncate(OFF_HEADER_CHECKSUM);
+    assert!(!cart.verify_header_checksum());
+}
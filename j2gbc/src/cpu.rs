@@ -1,36 +1,48 @@
 use std::{
     cmp::min,
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     num::Wrapping,
     ops::{Index, IndexMut},
     time::Duration,
 };
 
-use log::{debug, error};
-
 use crate::{
     alu::*,
     audio::AudioSink,
     cart::Cart,
     inst::{Arith, Bits, Control, Instruction, Load, Logic},
-    mem::{Address, MemDevice},
+    mem::{Address, AddressRange, MemDevice},
     mmu::Mmu,
 };
 
 pub const CLOCK_RATE: u64 = 4_194_304;
 pub const LONGEST_INSTRUCTION_CYCLE: u64 = 20; // LD (a16),SP
 
+mod coverage;
 mod interrupt;
 mod register;
 #[cfg(test)]
 mod test;
 
 pub use self::{
+    coverage::OpcodeCoverage,
     interrupt::{Interrupt, InterruptSet},
     register::{ConditionCode, Operand, Register16, Register8},
 };
 use crate::error::ExecutionError;
 
+pub type BreakpointCallback = Box<dyn FnMut(&mut Cpu) -> bool + Send>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BreakReason {
+    Breakpoint(Address),
+    Callback(Address),
+    Scanline(u8),
+    Interrupt(Interrupt),
+    StackBounds(Address),
+    Error(ExecutionError),
+}
+
 pub struct Cpu {
     registers: [u8; 8],
     pub pc: Address,
@@ -38,11 +50,24 @@ pub struct Cpu {
     pub mmu: Mmu,
     cycle: u64,
     pub interrupt_master_enable: bool,
+    // EI only takes effect once the instruction after it has run
+    interrupt_enable_pending: bool,
     halted: bool,
+    // HALT with an interrupt already pending and IME off doesn't halt, but
+    // the byte after it gets read twice
+    halt_bug: bool,
+    pub prefetch: bool,
+    // Opcode byte read ahead while the previous instruction executed
+    prefetched: Option<(Address, u8)>,
+    coverage: OpcodeCoverage,
 
     pub debug_halted: bool,
     pub breakpoints: HashSet<Address>,
     pub interrupt_breakpoints: HashSet<Interrupt>,
+    pub ly_breakpoints: HashSet<u8>,
+    pub stack_bounds: Option<AddressRange>,
+    breakpoint_callbacks: HashMap<Address, BreakpointCallback>,
+    break_reasons: Vec<BreakReason>,
 }
 
 impl Cpu {
@@ -53,7 +78,7 @@ impl Cpu {
             cgb_mode = c.supports_cgb_mode();
         }
 
-        debug!("CGB mode: {}", cgb_mode);
+        debug!(Cpu, "CGB mode: {}", cgb_mode);
 
         let mut cpu = Cpu {
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
@@ -62,11 +87,20 @@ impl Cpu {
             mmu: Mmu::new(c, audio_sink, cgb_mode),
             cycle: 0,
             interrupt_master_enable: false,
+            interrupt_enable_pending: false,
             halted: false,
+            halt_bug: false,
+            prefetch: false,
+            prefetched: None,
+            coverage: OpcodeCoverage::default(),
 
             debug_halted: false,
             breakpoints: initial_breakpoints,
             interrupt_breakpoints: HashSet::new(),
+            ly_breakpoints: HashSet::new(),
+            stack_bounds: None,
+            breakpoint_callbacks: HashMap::new(),
+            break_reasons: Vec::new(),
         };
 
         cpu[Register8::A] = if cgb_mode { 0x11 } else { 0x01 };
@@ -85,26 +119,48 @@ impl Cpu {
         self.cycle
     }
 
+    pub fn set_breakpoint_callback(&mut self, addr: Address, callback: BreakpointCallback) {
+        self.breakpoint_callbacks.insert(addr, callback);
+    }
+
+    pub fn remove_breakpoint_callback(&mut self, addr: Address) {
+        self.breakpoint_callbacks.remove(&addr);
+    }
+
+    pub fn take_break_reasons(&mut self) -> Vec<BreakReason> {
+        std::mem::take(&mut self.break_reasons)
+    }
+
     fn execute(&mut self, i: Instruction) -> Result<(), ExecutionError> {
         let mut branch_taken = false;
         match i {
             Instruction::Nop => {}
             Instruction::EnableInterrupts => {
-                self.interrupt_master_enable = true;
+                self.interrupt_enable_pending = true;
             }
             Instruction::DisableInterrupts => {
                 self.interrupt_master_enable = false;
+                self.interrupt_enable_pending = false;
             }
             Instruction::Stop => {
                 if self.mmu.prepared_speed_switch {
                     self.mmu.toggle_double_speed();
                 } else {
-                    error!("Stop executed without speed switch mode prepared");
+                    error!(Cpu, "Stop executed without speed switch mode prepared");
                     return Err(ExecutionError::StopWithoutSpeed);
                 }
             }
             Instruction::Halt => {
-                self.halted = true;
+                let ime = self.interrupt_master_enable || self.interrupt_enable_pending;
+                let pending =
+                    Interrupt::int_to_run(self.mmu.interrupt_flag, self.mmu.interrupt_enable)
+                        .0
+                        .is_some();
+                if !ime && pending {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
             }
             Instruction::SetCarry => {
                 let mut f = self.flags();
@@ -347,20 +403,17 @@ impl Cpu {
                 *branch_taken = true;
             }
             Control::Return => {
-                self.pc = Address(self.mmu.read16(self.sp)?);
-                self.sp += Address(2);
+                self.pc = Address(self.pop16()?);
                 *branch_taken = true;
             }
             Control::InterruptReturn => {
-                self.pc = Address(self.mmu.read16(self.sp)?);
-                self.sp += Address(2);
+                self.pc = Address(self.pop16()?);
                 self.interrupt_master_enable = true;
                 *branch_taken = true;
             }
             Control::ReturnConditional(cond) => {
                 if self.flags().matches(cond) {
-                    self.pc = Address(self.mmu.read16(self.sp)?);
-                    self.sp += Address(2);
+                    self.pc = Address(self.pop16()?);
                     *branch_taken = true;
                 }
             }
@@ -554,6 +607,10 @@ impl Cpu {
         }
     }
 
+    pub fn coverage(&self) -> &OpcodeCoverage {
+        &self.coverage
+    }
+
     pub fn run_cycle(&mut self) -> Result<(), ExecutionError> {
         self.fire_interrupts()?;
 
@@ -561,43 +618,233 @@ impl Cpu {
             return Ok(());
         }
 
-        if self.breakpoints.contains(&self.pc) {
-            self.breakpoints.remove(&self.pc);
-            error!("Breakpoint");
+        // Check every condition so all of the reasons for stopping here get reported
+        let pc = self.pc;
+        let mut reasons = Vec::new();
+        if let Some(mut callback) = self.breakpoint_callbacks.remove(&pc) {
+            // A callback that halts is consumed, just like a plain breakpoint
+            if callback(self) {
+                self.breakpoint_callbacks.entry(pc).or_insert(callback);
+            } else {
+                reasons.push(BreakReason::Callback(pc));
+            }
+        }
+
+        if self.breakpoints.remove(&pc) {
+            reasons.push(BreakReason::Breakpoint(pc));
+        }
+
+        let ly = self.mmu.lcd.ly();
+        if self.ly_breakpoints.remove(&ly) {
+            reasons.push(BreakReason::Scanline(ly));
+        }
+
+        if !reasons.is_empty() {
+            error!(Cpu, "Breakpoint: {:?}", reasons);
+            self.break_reasons.extend(reasons);
             return Err(ExecutionError::Breakpoint);
         }
 
-        let (instruction, len) = self.fetch_instruction(self.pc)?;
+        let (instruction, len) = self.fetch_next_instruction()?;
 
-        self.pc += Address(u16::from(len));
+        let sp_in_bounds = matches!(self.stack_bounds, Some(r) if self.sp.in_(r));
+        let enable_pending = self.interrupt_enable_pending;
+
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc += Address(u16::from(len) - 1);
+        } else {
+            self.pc += Address(u16::from(len));
+        }
         self.execute(instruction)?;
 
+        // A DI straight after EI cancels the pending enable
+        if enable_pending && self.interrupt_enable_pending {
+            self.interrupt_enable_pending = false;
+            self.interrupt_master_enable = true;
+        }
+
+        if sp_in_bounds && !matches!(self.stack_bounds, Some(r) if self.sp.in_(r)) {
+            error!(Cpu, "Stack pointer left its bounds: {}", self.sp);
+            self.break_reasons.push(BreakReason::StackBounds(self.sp));
+            self.drive_peripherals();
+            return Err(ExecutionError::Breakpoint);
+        }
+
         self.drive_peripherals();
         Ok(())
     }
 
-    pub fn run_for_duration(&mut self, duration: &Duration) {
+    pub fn run_for_duration(&mut self, duration: &Duration) -> Vec<BreakReason> {
         let cycles_to_run = duration_to_cycle_count(&duration);
         let stop_at_cycle = self.cycle() + cycles_to_run;
         self.mmu
             .lcd
             .set_running_until(stop_at_cycle + LONGEST_INSTRUCTION_CYCLE);
         while self.cycle() < stop_at_cycle && !self.debug_halted {
+            match self.run_cycle() {
+                Ok(()) => {}
+                Err(ExecutionError::Breakpoint) => self.debug_halted = true,
+                Err(e) => {
+                    self.break_reasons.push(BreakReason::Error(e));
+                    self.debug_halted = true;
+                }
+            }
+
+            if self.halted {
+                self.skip_halted_cycles(stop_at_cycle);
+            }
+        }
+
+        self.take_break_reasons()
+    }
+
+    pub fn run_for_frame(&mut self) {
+        let frame = self.mmu.lcd.frame_count();
+        self.mmu.lcd.set_running_until(self.cycle());
+        while self.mmu.lcd.frame_count() == frame && !self.debug_halted {
             if self.run_cycle().is_err() {
                 self.debug_halted = true;
             }
 
             if self.halted {
-                self.cycle = min(
-                    self.mmu.audio.synth.get_next_event_cycle(),
-                    min(
-                        self.mmu.lcd.get_next_event_cycle(),
-                        min(self.mmu.timer.get_next_event_cycle(), stop_at_cycle),
-                    ),
-                );
-                self.drive_peripherals();
+                self.skip_halted_cycles(u64::MAX);
+            }
+        }
+    }
+
+    pub fn run_instructions(&mut self, max: u64) -> u64 {
+        self.mmu.lcd.set_running_until(self.cycle());
+        let mut count = 0;
+        while count < max && !self.debug_halted {
+            // Time spent halted doesn't count against the limit
+            if self.halted {
+                if !self.can_wake_from_halt() {
+                    break;
+                }
+                self.skip_halted_cycles(u64::MAX);
+                continue;
+            }
+
+            if self.run_cycle().is_err() {
+                self.debug_halted = true;
+                break;
             }
+            count += 1;
         }
+
+        count
+    }
+
+    pub fn run_until_pc(&mut self, addr: Address, max_cycles: u64) -> Result<bool, ExecutionError> {
+        let stop_at_cycle = self.cycle() + max_cycles;
+        self.mmu.lcd.set_running_until(stop_at_cycle);
+        while self.pc != addr {
+            if self.cycle() >= stop_at_cycle {
+                return Ok(false);
+            }
+
+            self.run_cycle()?;
+            if self.halted {
+                self.skip_halted_cycles(stop_at_cycle);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn profile_region(&mut self, start: Address, end: Address, max_cycles: u64) -> Option<u64> {
+        let stop_at_cycle = self.cycle() + max_cycles;
+        self.mmu.lcd.set_running_until(self.cycle());
+        if self.pc != start && !self.step_until_pc(start, stop_at_cycle) {
+            return None;
+        }
+
+        // Only the first arrival at start begins the measurement, so loops back to it are counted
+        let start_cycle = self.cycle();
+        if !self.step_until_pc(end, stop_at_cycle) {
+            return None;
+        }
+        Some(self.cycle() - start_cycle)
+    }
+
+    // Always executes at least once, so a region can start and end at the same address
+    fn step_until_pc(&mut self, addr: Address, stop_at_cycle: u64) -> bool {
+        loop {
+            if self.cycle() >= stop_at_cycle {
+                return false;
+            }
+
+            if self.run_cycle().is_err() {
+                self.debug_halted = true;
+                return false;
+            }
+
+            if self.halted {
+                self.skip_halted_cycles(stop_at_cycle);
+            }
+
+            if self.pc == addr {
+                return true;
+            }
+        }
+    }
+
+    // Returns whether the instruction after the call was reached. Stops early on a breakpoint
+    // inside the callee, when the budget runs out, or when the CPU halts with nothing to wake it
+    pub fn step_over(&mut self, max_cycles: u64) -> Result<bool, ExecutionError> {
+        let stop_at_cycle = self.cycle() + max_cycles;
+        let (instruction, len) = self.fetch_instruction(self.pc)?;
+        let return_address = self.pc + Address(u16::from(len));
+        let return_sp = self.sp;
+
+        self.mmu.lcd.set_running_until(self.cycle());
+        self.run_cycle()?;
+        if !instruction.is_call() {
+            return Ok(true);
+        }
+
+        // Matching SP as well as PC keeps recursive calls from stopping early
+        while self.pc != return_address || self.sp != return_sp {
+            if self.cycle() >= stop_at_cycle || self.debug_halted {
+                return Ok(false);
+            }
+
+            match self.run_cycle() {
+                Ok(()) => {}
+                Err(ExecutionError::Breakpoint) => {
+                    self.debug_halted = true;
+                    return Ok(false);
+                }
+                Err(e) => return Err(e),
+            }
+
+            if self.halted {
+                if !self.can_wake_from_halt() {
+                    return Ok(false);
+                }
+                self.skip_halted_cycles(stop_at_cycle);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Only a button press could wake the CPU otherwise, and that can't happen mid-call
+    fn can_wake_from_halt(&self) -> bool {
+        let wakers = Interrupt::VBlank.bits() | Interrupt::LCDC.bits() | Interrupt::Timer.bits();
+        self.mmu.interrupt_enable & wakers != 0
+    }
+
+    fn skip_halted_cycles(&mut self, stop_at_cycle: u64) {
+        self.cycle = min(
+            self.mmu.audio.synth.get_next_event_cycle(),
+            min(
+                self.mmu.lcd.get_next_event_cycle(),
+                min(self.mmu.timer.get_next_event_cycle(), stop_at_cycle),
+            ),
+        );
+        self.drive_peripherals();
     }
 
     fn drive_peripherals(&mut self) {
@@ -610,6 +857,7 @@ impl Cpu {
     }
 
     fn request_interrupts(&mut self, ints: InterruptSet) {
+        self.mmu.log_interrupt_requests(ints.if_(), self.cycle);
         self.mmu.interrupt_flag |= ints.if_();
         if ints.if_() & self.mmu.interrupt_enable != 0 {
             self.halted = false;
@@ -625,7 +873,8 @@ impl Cpu {
                 self.fire_interrupt(int)?;
                 if self.interrupt_breakpoints.contains(&int) {
                     self.interrupt_breakpoints.remove(&int);
-                    debug!("Interrupt breakpoint {:?}", int);
+                    debug!(Cpu, "Interrupt breakpoint {:?}", int);
+                    self.break_reasons.push(BreakReason::Interrupt(int));
                     self.debug_halted = true;
                 }
             }
@@ -640,6 +889,7 @@ impl Cpu {
 
         self.pc = int.table_address();
         self.interrupt_master_enable = false;
+        self.prefetched = None;
 
         Ok(())
     }
@@ -653,6 +903,34 @@ impl Cpu {
         Instruction::decode(bytes)
     }
 
+    fn fetch_next_instruction(&mut self) -> Result<(Instruction, u8), ExecutionError> {
+        let mut bytes = [
+            self.mmu.read(self.pc)?,
+            self.mmu.read(self.pc + Address(1))?,
+            self.mmu.read(self.pc + Address(2))?,
+        ];
+        if let Some((a, opcode)) = self.prefetched.take() {
+            if a == self.pc {
+                bytes[0] = opcode;
+            }
+        }
+        // PC failed to advance past the opcode, so it's read again as the first operand
+        if self.halt_bug {
+            bytes = [bytes[0], bytes[0], bytes[1]];
+        }
+
+        let (instruction, len) = Instruction::decode(bytes)?;
+        self.coverage.record(bytes[0], bytes[1]);
+
+        // The following opcode is fetched before this instruction's writes land
+        if self.prefetch {
+            let next = self.pc + Address(u16::from(len) - u16::from(self.halt_bug));
+            self.prefetched = self.mmu.read(next).ok().map(|v| (next, v));
+        }
+
+        Ok((instruction, len))
+    }
+
     fn write_r16(&mut self, r: Register16, v: u16) {
         match r {
             Register16::SP => self.sp = Address(v),
@@ -687,17 +965,21 @@ impl Cpu {
         }
     }
 
+    // Matches the hardware order: high byte to SP-1 first, then low byte to SP-2
     fn push16(&mut self, v: u16) -> Result<(), ExecutionError> {
-        let nsp = self.sp - Address(2);
-        self.mmu.write16(nsp, v)?;
-        self.sp = nsp;
+        self.sp -= Address(1);
+        self.mmu.write(self.sp, (v >> 8) as u8)?;
+        self.sp -= Address(1);
+        self.mmu.write(self.sp, v as u8)?;
         Ok(())
     }
 
     fn pop16(&mut self) -> Result<u16, ExecutionError> {
-        let v = self.mmu.read16(self.sp)?;
-        self.sp += Address(2);
-        Ok(v)
+        let lo = self.mmu.read(self.sp)?;
+        self.sp += Address(1);
+        let hi = self.mmu.read(self.sp)?;
+        self.sp += Address(1);
+        Ok(hi_lo(hi, lo))
     }
 
     fn read_indirect(&self, r: Register16) -> Result<u8, ExecutionError> {
@@ -715,6 +997,8 @@ impl Cpu {
     }
 
     pub fn request_p1_int(&mut self) {
+        self.mmu
+            .log_interrupt_requests(Interrupt::Controller.bits(), self.cycle);
         self.mmu.interrupt_flag |= Interrupt::Controller.bits();
     }
 }
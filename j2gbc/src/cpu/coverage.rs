@@ -0,0 +1,38 @@
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpcodeCoverage {
+    base: [u64; 4],
+    cb: [u64; 4],
+}
+
+impl OpcodeCoverage {
+    pub fn record(&mut self, opcode: u8, cb_opcode: u8) {
+        set_bit(&mut self.base, opcode);
+        if opcode == 0xCB {
+            set_bit(&mut self.cb, cb_opcode);
+        }
+    }
+
+    pub fn is_covered(&self, opcode: u8) -> bool {
+        get_bit(&self.base, opcode)
+    }
+
+    pub fn is_cb_covered(&self, opcode: u8) -> bool {
+        get_bit(&self.cb, opcode)
+    }
+
+    pub fn covered(&self) -> Vec<u8> {
+        (0..=255).filter(|o| self.is_covered(*o)).collect()
+    }
+
+    pub fn cb_covered(&self) -> Vec<u8> {
+        (0..=255).filter(|o| self.is_cb_covered(*o)).collect()
+    }
+}
+
+fn set_bit(bits: &mut [u64; 4], i: u8) {
+    bits[i as usize / 64] |= 1 << (i % 64);
+}
+
+fn get_bit(bits: &[u64; 4], i: u8) -> bool {
+    bits[i as usize / 64] & (1 << (i % 64)) != 0
+}
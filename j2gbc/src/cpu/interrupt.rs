@@ -7,15 +7,17 @@ pub enum Interrupt {
     VBlank,
     LCDC,
     Timer,
+    Serial,
     Controller,
 }
 
 const INT_VBLANK: u8 = 0b0000_0001;
 const INT_LCDC: u8 = 0b0000_0010;
 const INT_TIMER: u8 = 0b0000_0100;
+const INT_SERIAL: u8 = 0b0000_1000;
 const INT_CONTROLLER: u8 = 0b0001_0000;
 
-const PRIORITY: [u8; 4] = [INT_VBLANK, INT_LCDC, INT_TIMER, INT_CONTROLLER];
+const PRIORITY: [u8; 5] = [INT_VBLANK, INT_LCDC, INT_TIMER, INT_SERIAL, INT_CONTROLLER];
 
 impl Interrupt {
     pub fn bits(self) -> u8 {
@@ -23,6 +25,7 @@ impl Interrupt {
             Interrupt::VBlank => INT_VBLANK,
             Interrupt::LCDC => INT_LCDC,
             Interrupt::Timer => INT_TIMER,
+            Interrupt::Serial => INT_SERIAL,
             Interrupt::Controller => INT_CONTROLLER,
         }
     }
@@ -32,6 +35,7 @@ impl Interrupt {
             Interrupt::VBlank => Address(0x0040),
             Interrupt::LCDC => Address(0x0048),
             Interrupt::Timer => Address(0x0050),
+            Interrupt::Serial => Address(0x0058),
             Interrupt::Controller => Address(0x0060),
         }
     }
@@ -41,6 +45,7 @@ impl Interrupt {
             INT_VBLANK => Interrupt::VBlank,
             INT_LCDC => Interrupt::LCDC,
             INT_TIMER => Interrupt::Timer,
+            INT_SERIAL => Interrupt::Serial,
             INT_CONTROLLER => Interrupt::Controller,
             _ => panic!("Unsupported interrupt {}", bit),
         }
@@ -83,3 +88,24 @@ impl From<Interrupt> for InterruptSet {
         InterruptSet { if_: intr.bits() }
     }
 }
+
+#[test]
+fn test_interrupt_bits_and_vectors() {
+    let expected = [
+        (Interrupt::VBlank, 0, 0x0040),
+        (Interrupt::LCDC, 1, 0x0048),
+        (Interrupt::Timer, 2, 0x0050),
+        (Interrupt::Serial, 3, 0x0058),
+        (Interrupt::Controller, 4, 0x0060),
+    ];
+
+    for (int, bit, vector) in expected.iter() {
+        assert_eq!(int.bits(), 1 << bit);
+        assert_eq!(int.table_address(), Address(*vector));
+        assert_eq!(Interrupt::from_bits(1 << bit), *int);
+        assert_eq!(
+            Interrupt::int_to_run(0xFF, 1 << bit),
+            (Some(*int), !(1 << bit))
+        );
+    }
+}
@@ -1,10 +1,15 @@
 use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
+use std::time::Duration;
 
-use super::{Arith, Cpu, Instruction, Load, Operand, Register16, Register8};
+use super::{
+    Arith, Bits, BreakReason, ConditionCode, Control, Cpu, Instruction, Interrupt, Load, Operand,
+    Register16, Register8,
+};
 use crate::alu::Flags;
 use crate::audio::NullSink;
-use crate::cart::Cart;
+use crate::cart::{Cart, LoadOptions};
+use crate::error::ExecutionError;
 use crate::mem::{Address, MemDevice};
 
 const INTIAL_PC: Address = Address(0x0150);
@@ -29,13 +34,55 @@ fn test_ei() {
 
     let i = Instruction::EnableInterrupts;
     cpu.execute(i).unwrap();
-    assert_eq!(cpu.interrupt_master_enable, true);
+    assert!(!cpu.interrupt_master_enable);
+    assert!(cpu.interrupt_enable_pending);
 
     assert_reg_vals(&cpu, &[]);
     assert_eq!(cpu.pc, INTIAL_PC);
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_ei_delay() {
+    let mut cpu = make_test_cpu();
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu.pc = Address(0xC000);
+    for (i, v) in [0xFB, 0x00, 0x00].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap(); // ei; nop; nop
+    }
+
+    cpu.run_cycle().unwrap();
+    assert!(!cpu.interrupt_master_enable);
+    cpu.run_cycle().unwrap();
+    assert!(cpu.interrupt_master_enable);
+    assert_eq!(cpu.pc, Address(0xC002));
+    assert_eq!(cpu.sp, INITAL_SP);
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.mmu.interrupt_flag, 0);
+    assert_eq!(cpu.pop16().unwrap(), 0xC002);
+}
+
+#[test]
+fn test_ei_then_di() {
+    let mut cpu = make_test_cpu();
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu.pc = Address(0xC000);
+    for (i, v) in [0xFB, 0xF3, 0x00].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap(); // ei; di; nop
+    }
+
+    for _ in 0..3 {
+        cpu.run_cycle().unwrap();
+    }
+    assert!(!cpu.interrupt_master_enable);
+    assert_eq!(cpu.pc, Address(0xC003));
+    assert_eq!(cpu.sp, INITAL_SP);
+    assert_eq!(cpu.mmu.interrupt_flag, Interrupt::VBlank.bits());
+}
+
 #[test]
 fn test_di() {
     let mut cpu = make_test_cpu();
@@ -64,6 +111,86 @@ fn test_halt() {
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_halt_bug() {
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = false;
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu[Register8::A] = 0;
+    cpu.pc = Address(0xC000);
+    for (i, v) in [0x76, 0x3C, 0x00].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap(); // halt; inc a; nop
+    }
+
+    cpu.run_cycle().unwrap();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, Address(0xC001));
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pc, Address(0xC001));
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pc, Address(0xC002));
+    assert_eq!(cpu[Register8::A], 2);
+    assert_eq!(cpu.mmu.interrupt_flag, Interrupt::VBlank.bits());
+
+    // The opcode is read twice, so `ld a,$14` loads its own opcode and 0x14 runs as `inc d`
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = false;
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu[Register8::D] = 0;
+    cpu.pc = Address(0xC000);
+    for (i, v) in [0x76, 0x3E, 0x14].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap(); // halt; ld a,$14
+    }
+
+    cpu.run_cycle().unwrap();
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu[Register8::A], 0x3E);
+    assert_eq!(cpu.pc, Address(0xC002));
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu[Register8::D], 1);
+    assert_eq!(cpu.pc, Address(0xC003));
+}
+
+#[test]
+fn test_halt_bug_prefetch() {
+    let mut cpu = make_test_cpu();
+    cpu.prefetch = true;
+    cpu.interrupt_master_enable = false;
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu.pc = Address(0xC000);
+    for (i, v) in [0x76, 0x3E, 0x14].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap(); // halt; ld a,$14
+    }
+
+    cpu.run_cycle().unwrap();
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.prefetched, Some((Address(0xC002), 0x14)));
+}
+
+#[test]
+fn test_timer_wakes_halt() {
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = true;
+    cpu.mmu.interrupt_enable = Interrupt::Timer.bits();
+    cpu.mmu.write(Address(0xFF07), 0b101).unwrap(); // 16 cycles per increment
+    cpu.mmu.write(Address(0xFF05), 0xFF).unwrap();
+    cpu.halted = true;
+
+    // The timer overflows well before the LCD's next mode change
+    while cpu.halted {
+        cpu.skip_halted_cycles(u64::MAX);
+    }
+    assert_eq!(cpu.cycle(), 16);
+    assert_eq!(cpu.mmu.interrupt_flag, Interrupt::Timer.bits());
+
+    cpu.run_cycle().unwrap();
+    assert_eq!(cpu.pop16().unwrap(), INTIAL_PC.0);
+}
+
 #[test]
 fn test_scf() {
     let mut cpu = make_test_cpu();
@@ -77,6 +204,54 @@ fn test_scf() {
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_scf_clears_n_and_h() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::F] = Flags(0).subtract().halfcarry().0;
+
+    cpu.execute(Instruction::SetCarry).unwrap();
+
+    assert_reg_vals(&cpu, &[(Register8::F, Flags(0).carry().0)]);
+}
+
+#[test]
+fn test_ccf() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::F] = Flags(0).zero().subtract().halfcarry().carry().0;
+
+    cpu.execute(Instruction::ClearCarry).unwrap();
+    assert_reg_vals(&cpu, &[(Register8::F, Flags(0).zero().0)]);
+
+    cpu[Register8::F] = Flags(0).subtract().halfcarry().0;
+    cpu.execute(Instruction::ClearCarry).unwrap();
+    assert_reg_vals(&cpu, &[(Register8::F, Flags(0).carry().0)]);
+    assert_eq!(cpu.pc, INTIAL_PC);
+    assert_eq!(cpu.sp, INITAL_SP);
+}
+
+#[test]
+fn test_cpl() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::A] = 0x35;
+    cpu[Register8::F] = Flags(0).zero().carry().0;
+
+    let i = Instruction::Bits(Bits::Complement);
+    cpu.execute(i).unwrap();
+
+    assert_reg_vals(
+        &cpu,
+        &[
+            (Register8::A, 0xCA),
+            (
+                Register8::F,
+                Flags(0).zero().subtract().halfcarry().carry().0,
+            ),
+        ],
+    );
+    assert_eq!(cpu.pc, INTIAL_PC);
+    assert_eq!(cpu.sp, INITAL_SP);
+}
+
 #[test]
 fn test_cpi() {
     let mut cpu = make_test_cpu();
@@ -117,6 +292,77 @@ fn test_cpr() {
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_breakpoint_callback() {
+    let mut cpu = make_test_cpu();
+
+    cpu.set_breakpoint_callback(
+        INTIAL_PC,
+        Box::new(|cpu| {
+            cpu[Register8::A] = 0x99;
+            true
+        }),
+    );
+    cpu.run_cycle().unwrap();
+
+    assert_reg_vals(&cpu, &[(Register8::A, 0x99)]);
+    assert_eq!(cpu.pc, INTIAL_PC + Address(1));
+
+    cpu.set_breakpoint_callback(cpu.pc, Box::new(|_| false));
+    match cpu.run_cycle() {
+        Err(ExecutionError::Breakpoint) => {}
+        _ => panic!("Expected the callback to halt execution"),
+    }
+    assert_eq!(cpu.pc, INTIAL_PC + Address(1));
+}
+
+#[test]
+fn test_multiple_break_reasons() {
+    let mut cpu = make_test_cpu();
+
+    cpu.breakpoints.insert(INTIAL_PC);
+    cpu.ly_breakpoints.insert(0);
+    let reasons = cpu.run_for_duration(&Duration::from_millis(1));
+
+    assert!(cpu.debug_halted);
+    assert_eq!(cpu.pc, INTIAL_PC);
+    assert_eq!(
+        reasons,
+        vec![BreakReason::Breakpoint(INTIAL_PC), BreakReason::Scanline(0)]
+    );
+}
+
+#[test]
+fn test_push_write_order() {
+    let mut cpu = make_test_cpu();
+    cpu.sp = Address(0xD000);
+    cpu[Register8::B] = 0x12;
+    cpu[Register8::C] = 0x34;
+
+    cpu.execute(Instruction::Load(Load::Push(Register16::BC)))
+        .unwrap();
+    assert_eq!(cpu.mmu.read(Address(0xCFFF)).unwrap(), 0x12);
+    assert_eq!(cpu.mmu.read(Address(0xCFFE)).unwrap(), 0x34);
+    assert_eq!(cpu.sp, Address(0xCFFE));
+
+    // The high byte is written before the low byte faults
+    cpu.sp = Address(0xD100);
+    cpu.mmu.watchpoints.insert(Address(0xD0FE));
+    assert!(cpu
+        .execute(Instruction::Load(Load::Push(Register16::BC)))
+        .is_err());
+    cpu.mmu.watchpoints.clear();
+    assert_eq!(cpu.mmu.read(Address(0xD0FF)).unwrap(), 0x12);
+    assert_eq!(cpu.mmu.read(Address(0xD0FE)).unwrap(), 0x00);
+
+    cpu.sp = Address(0xCFFE);
+    cpu.execute(Instruction::Load(Load::Pop(Register16::DE)))
+        .unwrap();
+    assert_eq!(cpu[Register8::D], 0x12);
+    assert_eq!(cpu[Register8::E], 0x34);
+    assert_eq!(cpu.sp, Address(0xD000));
+}
+
 // --------------- Arith Instructions ------------------
 
 #[test]
@@ -164,6 +410,330 @@ fn test_addr() {
     assert_eq!(cpu.sp, INITAL_SP);
 }
 
+#[test]
+fn test_inc_dec_sp_flags() {
+    let mut cpu = make_test_cpu();
+    let all_flags = Flags(0).zero().subtract().halfcarry().carry().0;
+    cpu[Register8::F] = all_flags;
+
+    cpu.sp = Address(0xFFFF);
+    cpu.execute(Instruction::Arith(Arith::IncrementRegister16(
+        Register16::SP,
+    )))
+    .unwrap();
+    assert_eq!(cpu.sp, Address(0x0000));
+    assert_reg_vals(&cpu, &[(Register8::F, all_flags)]);
+
+    cpu.execute(Instruction::Arith(Arith::DecrementRegister16(
+        Register16::SP,
+    )))
+    .unwrap();
+    assert_eq!(cpu.sp, Address(0xFFFF));
+    assert_reg_vals(&cpu, &[(Register8::F, all_flags)]);
+
+    cpu[Register8::F] = 0;
+    cpu.sp = Address(0x0000);
+    cpu.execute(Instruction::Arith(Arith::DecrementRegister16(
+        Register16::SP,
+    )))
+    .unwrap();
+    assert_eq!(cpu.sp, Address(0xFFFF));
+    assert_reg_vals(&cpu, &[(Register8::F, 0)]);
+}
+
+#[test]
+fn test_sp_relative_offsets() {
+    // (sp, offset, result, halfcarry, carry)
+    let cases = [
+        (0xFFF8, -1, 0xFFF7, true, true),
+        (0x0000, -1, 0xFFFF, false, false),
+        (0x00FF, 2, 0x0101, true, true),
+        (0x0F0F, 1, 0x0F10, true, false),
+        (0xD010, -16, 0xD000, false, true),
+    ];
+
+    for &(sp, offset, result, halfcarry, carry) in cases.iter() {
+        let mut cpu = make_test_cpu();
+        cpu.sp = Address(sp);
+        cpu[Register8::F] = Flags(0).zero().subtract().0;
+
+        // ld hl, sp+r8
+        let (i, _) = Instruction::decode([0xF8, offset as u8, 0x00]).unwrap();
+        cpu.execute(i).unwrap();
+        assert_eq!(cpu.read_r16(Register16::HL), result);
+        assert_eq!(cpu.sp, Address(sp));
+        let hl_flags = cpu[Register8::F];
+
+        // add sp, r8
+        cpu[Register8::F] = Flags(0).zero().subtract().0;
+        let (i, _) = Instruction::decode([0xE8, offset as u8, 0x00]).unwrap();
+        cpu.execute(i).unwrap();
+        assert_eq!(cpu.sp, Address(result));
+        assert_eq!(cpu[Register8::F], hl_flags);
+
+        let f = Flags(cpu[Register8::F]);
+        assert!(!f.get_zero());
+        assert!(!f.get_subtract());
+        assert_eq!(f.get_halfcarry(), halfcarry, "sp {:#x} {}", sp, offset);
+        assert_eq!(f.get_carry(), carry, "sp {:#x} {}", sp, offset);
+    }
+}
+
+#[test]
+fn test_daa_after_add() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::A] = 0x09;
+    cpu[Register8::B] = 0x01;
+
+    cpu.execute(Instruction::Arith(Arith::Add(Operand::Register(
+        Register8::B,
+    ))))
+    .unwrap();
+    cpu.execute(Instruction::Arith(Arith::DecimalAdjustAccumulator))
+        .unwrap();
+
+    assert_reg_vals(
+        &cpu,
+        &[
+            (Register8::A, 0x10),
+            (Register8::B, 0x01),
+            (Register8::F, Flags(0).0),
+        ],
+    );
+}
+
+#[test]
+fn test_swap_register() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::C] = 0xAB;
+    cpu[Register8::F] = Flags(0).zero().subtract().halfcarry().carry().0;
+
+    // swap c
+    let (i, _) = Instruction::decode([0xCB, 0x31, 0x00]).unwrap();
+    assert_eq!(
+        i,
+        Instruction::Bits(Bits::Swap(Operand::Register(Register8::C)))
+    );
+    cpu.execute(i).unwrap();
+
+    assert_reg_vals(&cpu, &[(Register8::C, 0xBA), (Register8::F, 0)]);
+}
+
+#[test]
+fn test_bit() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::D] = 0b1010_0101;
+    cpu[Register8::H] = 0xC0;
+    cpu[Register8::L] = 0x00;
+    cpu.mmu.write(Address(0xC000), 0b0000_1000).unwrap();
+
+    for b in 0..8 {
+        for &carry in &[false, true] {
+            let mut f = Flags(0).subtract();
+            f.set_carry(carry);
+            cpu[Register8::F] = f.0;
+            // bit b, d
+            let (i, _) = Instruction::decode([0xCB, 0x42 | b << 3, 0x00]).unwrap();
+            cpu.execute(i).unwrap();
+
+            let f = Flags(cpu[Register8::F]);
+            assert_eq!(f.get_zero(), 0b1010_0101 & (1 << b) == 0, "bit {}", b);
+            assert!(f.get_halfcarry());
+            assert!(!f.get_subtract());
+            assert_eq!(f.get_carry(), carry);
+        }
+
+        // bit b, (hl)
+        let (i, _) = Instruction::decode([0xCB, 0x46 | b << 3, 0x00]).unwrap();
+        cpu.execute(i).unwrap();
+        assert_eq!(Flags(cpu[Register8::F]).get_zero(), b != 3, "bit {}", b);
+    }
+    assert_eq!(cpu[Register8::D], 0b1010_0101);
+}
+
+#[test]
+fn test_set() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::E] = 0x00;
+    cpu[Register8::H] = 0xC0;
+    cpu[Register8::L] = 0x00;
+    cpu.mmu.write(Address(0xC000), 0x00).unwrap();
+    cpu[Register8::F] = Flags(0).zero().carry().0;
+
+    // set 3, e
+    let (i, _) = Instruction::decode([0xCB, 0xDB, 0x00]).unwrap();
+    cpu.execute(i).unwrap();
+    assert_eq!(cpu[Register8::E], 0x08);
+
+    // set 7, (hl)
+    let (i, _) = Instruction::decode([0xCB, 0xFE, 0x00]).unwrap();
+    cpu.execute(i).unwrap();
+    assert_eq!(cpu.mmu.read(Address(0xC000)).unwrap(), 0x80);
+
+    assert_eq!(cpu[Register8::F], Flags(0).zero().carry().0);
+}
+
+#[test]
+fn test_cb_rotate_through_carry() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::H] = 0xC0;
+    cpu[Register8::L] = 0x00;
+    cpu.mmu.write(Address(0xC000), 0x80).unwrap();
+    cpu[Register8::F] = Flags(0).carry().0;
+
+    // rl (hl)
+    let (i, _) = Instruction::decode([0xCB, 0x16, 0x00]).unwrap();
+    cpu.execute(i).unwrap();
+    assert_eq!(cpu.mmu.read(Address(0xC000)).unwrap(), 0x01);
+    assert_reg_vals(
+        &cpu,
+        &[
+            (Register8::H, 0xC0),
+            (Register8::L, 0x00),
+            (Register8::F, Flags(0).carry().0),
+        ],
+    );
+
+    // rr b
+    cpu[Register8::B] = 0x01;
+    let (i, _) = Instruction::decode([0xCB, 0x18, 0x00]).unwrap();
+    cpu.execute(i).unwrap();
+    assert_reg_vals(
+        &cpu,
+        &[
+            (Register8::B, 0x80),
+            (Register8::H, 0xC0),
+            (Register8::L, 0x00),
+            (Register8::F, Flags(0).carry().0),
+        ],
+    );
+}
+
+// --------------- Control Instructions ------------------
+#[test]
+fn test_reti() {
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = false;
+    cpu.push16(0x1234).unwrap();
+
+    cpu.execute(Instruction::Control(Control::InterruptReturn))
+        .unwrap();
+    assert_eq!(cpu.pc, Address(0x1234));
+    assert_eq!(cpu.sp, INITAL_SP);
+    assert!(cpu.interrupt_master_enable);
+
+    cpu.mmu.interrupt_enable = Interrupt::VBlank.bits();
+    cpu.mmu.interrupt_flag = Interrupt::VBlank.bits();
+    cpu.fire_interrupts().unwrap();
+    assert_eq!(cpu.pc, Interrupt::VBlank.table_address());
+    assert_eq!(cpu.mmu.interrupt_flag, 0);
+    assert!(!cpu.interrupt_master_enable);
+}
+
+#[test]
+fn test_manual_interrupt_flag() {
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = true;
+    cpu.mmu
+        .write(Address(0xFFFF), Interrupt::Timer.bits())
+        .unwrap();
+    cpu.mmu
+        .write(
+            Address(0xFF0F),
+            Interrupt::VBlank.bits() | Interrupt::Timer.bits(),
+        )
+        .unwrap();
+
+    // Only the enabled Timer interrupt runs, and only its flag is cleared
+    cpu.fire_interrupts().unwrap();
+    assert_eq!(cpu.pc, Interrupt::Timer.table_address());
+    assert_eq!(cpu.pop16().unwrap(), INTIAL_PC.0);
+    assert_eq!(
+        cpu.mmu.read(Address(0xFF0F)).unwrap(),
+        0b1110_0000 | Interrupt::VBlank.bits()
+    );
+}
+
+#[test]
+fn test_interrupt_priority() {
+    let mut cpu = make_test_cpu();
+    cpu.interrupt_master_enable = true;
+    cpu.mmu.interrupt_enable = 0b0001_1111;
+    cpu.mmu.interrupt_flag = Interrupt::Timer.bits() | Interrupt::VBlank.bits();
+
+    cpu.fire_interrupts().unwrap();
+    assert_eq!(cpu.pc, Interrupt::VBlank.table_address());
+    assert_eq!(cpu.mmu.interrupt_flag, Interrupt::Timer.bits());
+
+    cpu.interrupt_master_enable = true;
+    cpu.fire_interrupts().unwrap();
+    assert_eq!(cpu.pc, Interrupt::Timer.table_address());
+    assert_eq!(cpu.mmu.interrupt_flag, 0);
+    assert_eq!(cpu.pop16().unwrap(), Interrupt::VBlank.table_address().0);
+}
+
+#[test]
+fn test_rst() {
+    let mut cpu = make_test_cpu();
+
+    let (i, len) = Instruction::decode([0xEF, 0x00, 0x00]).unwrap();
+    assert_eq!(i, Instruction::Control(Control::Reset(Address(0x0028))));
+    assert_eq!(len, 1);
+    cpu.execute(i).unwrap();
+
+    assert_eq!(cpu.pc, Address(0x0028));
+    assert_eq!(cpu.sp, INITAL_SP - Address(2));
+    assert_eq!(cpu.pop16().unwrap(), INTIAL_PC.0);
+}
+
+#[test]
+fn test_ret_conditional() {
+    let cases = [
+        (ConditionCode::NotZero, Flags(0).carry(), Flags(0).zero()),
+        (ConditionCode::Zero, Flags(0).zero(), Flags(0).carry()),
+        (ConditionCode::NotCarry, Flags(0).zero(), Flags(0).carry()),
+        (ConditionCode::Carry, Flags(0).carry(), Flags(0).zero()),
+    ];
+
+    for (cond, taken, not_taken) in cases.iter() {
+        let i = Instruction::Control(Control::ReturnConditional(*cond));
+
+        let mut cpu = make_test_cpu();
+        cpu.push16(0x1234).unwrap();
+        cpu[Register8::F] = taken.0;
+        let start = cpu.cycle();
+        cpu.execute(i).unwrap();
+        assert_eq!(cpu.pc, Address(0x1234), "{}", cond);
+        assert_eq!(cpu.sp, INITAL_SP);
+        assert_eq!(cpu.cycle() - start, 20);
+
+        let mut cpu = make_test_cpu();
+        cpu.push16(0x1234).unwrap();
+        cpu[Register8::F] = not_taken.0;
+        let start = cpu.cycle();
+        cpu.execute(i).unwrap();
+        assert_eq!(cpu.pc, INTIAL_PC, "{}", cond);
+        assert_eq!(cpu.sp, INITAL_SP - Address(2));
+        assert_eq!(cpu.cycle() - start, 8);
+    }
+}
+
+#[test]
+fn test_jp_hl() {
+    let mut cpu = make_test_cpu();
+    cpu[Register8::H] = 0x40;
+    cpu[Register8::L] = 0x00;
+
+    let (i, len) = Instruction::decode([0xE9, 0x00, 0x00]).unwrap();
+    assert_eq!(i, Instruction::Control(Control::JumpIndirect));
+    assert_eq!(len, 1);
+    let start = cpu.cycle();
+    cpu.execute(i).unwrap();
+
+    assert_eq!(cpu.pc, Address(0x4000));
+    assert_eq!(cpu.cycle() - start, 4);
+}
+
 // --------------- Load Instructions ------------------
 
 #[test]
@@ -221,7 +791,8 @@ fn test_load_indirect_decrement() {
 fn make_test_cpu() -> Cpu {
     let mut v = Vec::new();
     v.resize(1024, 0);
-    let mock_cart = Cart::load(Cursor::new(v)).expect("Failed to create mock cart");
+    let mock_cart =
+        Cart::load(Cursor::new(v), &LoadOptions::default()).expect("Failed to create mock cart");
     let mut cpu = Cpu::new(mock_cart, Box::new(NullSink), false);
     cpu.pc = INTIAL_PC;
     for (r, v) in reg_defaults().iter() {
@@ -272,3 +843,46 @@ fn assert_reg_vals(cpu: &Cpu, vals: &[(Register8, u8)]) {
         assert_eq!(cpu[*r], *defaults.get(&r).unwrap());
     }
 }
+
+#[test]
+fn test_prefetch_self_modifying() {
+    let run = |prefetch| {
+        let mut cpu = make_test_cpu();
+        cpu.prefetch = prefetch;
+        // ld (hl), a ; inc b -- the store turns the inc b into inc a
+        cpu.mmu.write(Address(0xC000), 0x77).unwrap();
+        cpu.mmu.write(Address(0xC001), 0x04).unwrap();
+        cpu.pc = Address(0xC000);
+        cpu[Register8::A] = 0x3C;
+        cpu[Register8::B] = 0;
+        cpu[Register8::H] = 0xC0;
+        cpu[Register8::L] = 0x01;
+
+        cpu.run_cycle().unwrap();
+        cpu.run_cycle().unwrap();
+        assert_eq!(cpu.mmu.read(Address(0xC001)).unwrap(), 0x3C);
+        assert_eq!(cpu.pc, Address(0xC002));
+        (cpu[Register8::A], cpu[Register8::B])
+    };
+
+    assert_eq!(run(true), (0x3C, 1));
+    assert_eq!(run(false), (0x3D, 0));
+}
+
+#[test]
+fn test_opcode_coverage() {
+    let mut cpu = make_test_cpu();
+    // nop; inc a; swap a; inc b
+    for (i, v) in [0x00, 0x3C, 0xCB, 0x37, 0x04].iter().enumerate() {
+        cpu.mmu.write(Address(0xC000 + i as u16), *v).unwrap();
+    }
+    cpu.pc = Address(0xC000);
+
+    for _ in 0..4 {
+        cpu.run_cycle().unwrap();
+    }
+
+    assert_eq!(cpu.coverage().covered(), vec![0x00, 0x04, 0x3C, 0xCB]);
+    assert_eq!(cpu.coverage().cb_covered(), vec![0x37]);
+    assert!(!cpu.coverage().is_covered(0x05));
+}
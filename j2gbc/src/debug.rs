@@ -1,6 +1,17 @@
 use crate::error::ExecutionError;
-use crate::{cpu::Cpu, lcd::fb::Framebuffer, mem::MemDevice};
-pub use crate::{cpu::Register8, inst::Instruction, lcd::BG_SIZE, mem::Address};
+use crate::{
+    audio::SquareChannel,
+    cpu::{BreakpointCallback, Cpu},
+    lcd::fb::Framebuffer,
+    mem::MemDevice,
+};
+pub use crate::{
+    cpu::{BreakReason, Interrupt, OpcodeCoverage, Register8},
+    inst::{decode_block, Instruction},
+    lcd::{fb::Pixel, BgBuffer, FrameRow, BG_SIZE},
+    mem::{register_name, Address, AddressRange},
+    mmu::{InterruptEvent, IoAccess, IoHook},
+};
 
 pub struct Debugger<'a> {
     cpu: &'a mut Cpu,
@@ -27,10 +38,41 @@ impl<'a> Debugger<'a> {
         self.cpu.interrupt_master_enable
     }
 
+    pub fn coverage(&self) -> OpcodeCoverage {
+        *self.cpu.coverage()
+    }
+
+    // Only channels 1 and 2 are square channels
+    pub fn square_phase(&self, channel: u8) -> Option<usize> {
+        self.square_channel(channel)
+            .map(SquareChannel::current_phase)
+    }
+
+    pub fn square_duty_pattern(&self, channel: u8) -> Option<[f32; 8]> {
+        self.square_channel(channel)
+            .map(SquareChannel::duty_pattern)
+    }
+
+    fn square_channel(&self, channel: u8) -> Option<&SquareChannel> {
+        match channel {
+            1 => Some(&self.cpu.mmu.audio.synth.chan1),
+            2 => Some(&self.cpu.mmu.audio.synth.chan2),
+            _ => None,
+        }
+    }
+
     pub fn read_mem(&self, addr: Address) -> Result<u8, ExecutionError> {
         self.cpu.mmu.read(addr)
     }
 
+    pub fn write_mem(&mut self, addr: Address, v: u8) -> Result<(), ExecutionError> {
+        self.cpu.mmu.write(addr, v)
+    }
+
+    pub fn read_io_registers(&self) -> [u8; 0x80] {
+        self.cpu.mmu.io_registers()
+    }
+
     pub fn is_halted_on_debugger(&self) -> bool {
         self.cpu.debug_halted
     }
@@ -60,11 +102,91 @@ impl<'a> Debugger<'a> {
         self.cpu.breakpoints.remove(&addr);
     }
 
+    pub fn set_breakpoint_callback(&mut self, addr: Address, callback: BreakpointCallback) {
+        self.cpu.set_breakpoint_callback(addr, callback);
+    }
+
+    pub fn remove_breakpoint_callback(&mut self, addr: Address) {
+        self.cpu.remove_breakpoint_callback(addr);
+    }
+
+    pub fn set_stack_bounds(&mut self, bounds: Option<AddressRange>) {
+        self.cpu.stack_bounds = bounds;
+    }
+
+    pub fn set_io_hook(&mut self, addr: Address, hook: IoHook) {
+        self.cpu.mmu.set_io_hook(addr, hook);
+    }
+
+    pub fn remove_io_hook(&mut self, addr: Address) {
+        self.cpu.mmu.remove_io_hook(addr);
+    }
+
+    pub fn set_interrupt_log(&mut self, enabled: bool) {
+        self.cpu.mmu.set_interrupt_log(enabled);
+    }
+
+    pub fn drain_interrupt_log(&mut self) -> Vec<InterruptEvent> {
+        self.cpu.mmu.drain_interrupt_log()
+    }
+
+    pub fn add_ly_breakpoint(&mut self, ly: u8) {
+        self.cpu.ly_breakpoints.insert(ly);
+    }
+
+    pub fn remove_ly_breakpoint(&mut self, ly: u8) {
+        self.cpu.ly_breakpoints.remove(&ly);
+    }
+
+    pub fn take_break_reasons(&mut self) -> Vec<BreakReason> {
+        self.cpu.take_break_reasons()
+    }
+
     pub fn get_breakpoints(&self) -> impl Iterator<Item = &Address> {
         self.cpu.breakpoints.iter()
     }
 
+    pub fn dirty_tiles_since_last_frame(&mut self) -> Vec<usize> {
+        self.cpu.mmu.lcd.dirty_tiles_since_last_frame()
+    }
+
+    pub fn dump_tilemap(&self, first: bool) -> [[u8; 32]; 32] {
+        self.cpu.mmu.lcd.dump_tilemap(first)
+    }
+
+    pub fn resolve_tile_index(&self, char_: u8) -> usize {
+        self.cpu.mmu.lcd.bg_tile_index(char_)
+    }
+
+    pub fn render_scanline(&self, ly: u8) -> FrameRow {
+        self.cpu.mmu.lcd.render_scanline(ly)
+    }
+
+    pub fn render_both_bg_maps(&self) -> (Box<BgBuffer>, Box<BgBuffer>) {
+        self.cpu.mmu.lcd.render_both_bg_maps()
+    }
+
+    pub fn all_palettes(&self) -> (Vec<[Pixel; 4]>, Vec<[Pixel; 4]>) {
+        self.cpu.mmu.lcd.all_palettes()
+    }
+
+    pub fn render_all_tiles(&self, palette: [Pixel; 4]) -> (Vec<Pixel>, (usize, usize)) {
+        self.cpu.mmu.lcd.render_all_tiles(palette)
+    }
+
     pub fn render_bg_to_fb(&self, index: usize, output: &mut Framebuffer) {
         self.cpu.mmu.lcd.render_bg_to_fb(index, output);
     }
+
+    pub fn selected_directions(&self) -> bool {
+        self.cpu.mmu.input.selected_directions()
+    }
+
+    pub fn selected_actions(&self) -> bool {
+        self.cpu.mmu.input.selected_actions()
+    }
+
+    pub fn pressed_buttons(&self) -> u8 {
+        self.cpu.mmu.input.pressed_buttons()
+    }
 }
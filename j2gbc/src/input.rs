@@ -35,6 +35,14 @@ impl Button {
         }
     }
 
+    // Bit in the combined pressed_buttons() mask, directions low and actions high
+    fn pressed_bit(self) -> u8 {
+        match self {
+            Button::Right | Button::Left | Button::Up | Button::Down => self.output(),
+            Button::A | Button::B | Button::Select | Button::Start => self.output() << 4,
+        }
+    }
+
     fn output(self) -> u8 {
         match self {
             Button::Right | Button::A => P10,
@@ -84,6 +92,21 @@ impl Input {
         self.active.remove(&button);
         self.recalculate();
     }
+
+    pub fn selected_directions(&self) -> bool {
+        (self.p1 & P14) == 0
+    }
+
+    pub fn selected_actions(&self) -> bool {
+        (self.p1 & P15) == 0
+    }
+
+    pub fn pressed_buttons(&self) -> u8 {
+        self.active
+            .iter()
+            .map(|b| b.pressed_bit())
+            .fold(0, u8::bitor)
+    }
 }
 
 impl MemDevice for Input {
@@ -102,3 +125,19 @@ impl MemDevice for Input {
         Ok(())
     }
 }
+
+#[test]
+fn test_selected_group() {
+    let mut input = Input::new();
+    assert!(!input.selected_directions());
+    assert!(!input.selected_actions());
+
+    input.write(REG_P1, P15).unwrap();
+    assert!(input.selected_directions());
+    assert!(!input.selected_actions());
+
+    input.activate_button(Button::Down);
+    input.activate_button(Button::A);
+    assert_eq!(input.pressed_buttons(), P13 | (P10 << 4));
+    assert_eq!(input.read(REG_P1).unwrap() & INPUT_MASK, INPUT_MASK & !P13);
+}
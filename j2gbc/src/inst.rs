@@ -1,8 +1,7 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Display;
 
-use log::error;
-
 use super::alu::hi_lo;
 use super::cpu::{ConditionCode, Operand, Register16, Register8};
 use super::mem::Address;
@@ -61,6 +60,13 @@ impl Instruction {
         }
     }
 
+    pub fn is_call(self) -> bool {
+        match self {
+            Instruction::Control(c) => c.is_call(),
+            _ => false,
+        }
+    }
+
     pub fn decode(bytes: [u8; 3]) -> Result<(Instruction, u8), ExecutionError> {
         match bytes[0] {
             0 => Ok((Instruction::Nop, 1)),
@@ -72,8 +78,8 @@ impl Instruction {
                 0x00 => Ok((Instruction::Stop, 2)),
                 _ => {
                     error!(
-                        "Unknown instruction {:#X} {:#X} {:#X}",
-                        bytes[0], bytes[1], bytes[2]
+                        Cpu,
+                        "Unknown instruction {:#X} {:#X} {:#X}", bytes[0], bytes[1], bytes[2]
                     );
                     Err(ExecutionError::InvalidInstruction)
                 }
@@ -455,8 +461,8 @@ impl Instruction {
             },
             0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
                 error!(
-                    "Unknown instruction {:#X} {:#X} {:#X}",
-                    bytes[0], bytes[1], bytes[2]
+                    Cpu,
+                    "Unknown instruction {:#X} {:#X} {:#X}", bytes[0], bytes[1], bytes[2]
                 );
                 Err(ExecutionError::InvalidInstruction)
             }
@@ -464,6 +470,35 @@ impl Instruction {
     }
 }
 
+// Decodes straight through `bytes`, stopping at the first invalid or truncated instruction, or
+// once the addresses run past the top of the address space
+pub fn decode_block(bytes: &[u8], start: Address) -> Vec<(Address, Instruction, u8)> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let byte_at = |i: usize| bytes.get(offset + i).cloned().unwrap_or(0);
+        let (instruction, len) = match Instruction::decode([byte_at(0), byte_at(1), byte_at(2)]) {
+            Ok(decoded) => decoded,
+            Err(_) => break,
+        };
+        if offset + len as usize > bytes.len() {
+            break;
+        }
+
+        let address = match u16::try_from(offset)
+            .ok()
+            .and_then(|o| start.0.checked_add(o))
+        {
+            Some(a) => Address(a),
+            None => break,
+        };
+
+        out.push((address, instruction, len));
+        offset += len as usize;
+    }
+    out
+}
+
 fn get_bits_bit(i: u8) -> u8 {
     (i >> 3) & 0b111
 }
@@ -487,3 +522,53 @@ impl Display for Instruction {
         }
     }
 }
+
+#[test]
+fn test_decode_block() {
+    let bytes = [
+        0x00, // nop
+        0x3E, 0x42, // ld a, $42
+        0xCB, 0x37, // swap a
+        0xC3, 0x50, 0x01, // jp $0150
+        0xD3, // invalid
+        0x00,
+    ];
+    let decoded = decode_block(&bytes, Address(0x150));
+    assert_eq!(
+        decoded,
+        vec![
+            (Address(0x150), Instruction::Nop, 1),
+            (
+                Address(0x151),
+                Instruction::Load(Load::Load(
+                    Operand::Register(Register8::A),
+                    Operand::Immediate(0x42)
+                )),
+                2
+            ),
+            (
+                Address(0x153),
+                Instruction::Bits(Bits::Swap(Operand::Register(Register8::A))),
+                2
+            ),
+            (
+                Address(0x155),
+                Instruction::Control(Control::Jump(Address(0x150))),
+                3
+            ),
+        ]
+    );
+
+    // The immediate operand runs off the end
+    assert_eq!(decode_block(&[0x00, 0x3E], Address(0)).len(), 1);
+
+    // Only the instructions that start at or below 0xFFFF are decoded
+    assert_eq!(
+        decode_block(&[0x00, 0x00, 0x00, 0x00], Address(0xFFFE)),
+        vec![
+            (Address(0xFFFE), Instruction::Nop, 1),
+            (Address(0xFFFF), Instruction::Nop, 1),
+        ]
+    );
+    assert_eq!(decode_block(&vec![0; 0x10001], Address(0)).len(), 0x10000);
+}
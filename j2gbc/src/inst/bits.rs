@@ -41,8 +41,10 @@ impl Bits {
             | Bits::ShiftRightArithmetic(Operand::Register(_))
             | Bits::ShiftRightLogical(Operand::Register(_)) => 8,
 
+            // BIT only reads (HL), everything else also writes it back
+            Bits::GetBit(_, Operand::IndirectRegister(_)) => 12,
+
             Bits::SetBit(_, Operand::IndirectRegister(_))
-            | Bits::GetBit(_, Operand::IndirectRegister(_))
             | Bits::ResetBit(_, Operand::IndirectRegister(_))
             | Bits::Swap(Operand::IndirectRegister(_))
             | Bits::RotateLeft(Operand::IndirectRegister(_))
@@ -51,7 +53,7 @@ impl Bits {
             | Bits::RotateRightCarry(Operand::IndirectRegister(_))
             | Bits::ShiftLeftArithmetic(Operand::IndirectRegister(_))
             | Bits::ShiftRightArithmetic(Operand::IndirectRegister(_))
-            | Bits::ShiftRightLogical(Operand::IndirectRegister(_)) => 8,
+            | Bits::ShiftRightLogical(Operand::IndirectRegister(_)) => 16,
 
             Bits::RotateRightAccumulator
             | Bits::RotateRightCarryAccumulator
@@ -94,3 +96,14 @@ impl Display for Bits {
         }
     }
 }
+
+#[test]
+fn test_cb_cycles() {
+    use crate::cpu::{Register16, Register8};
+
+    let hl = Operand::IndirectRegister(Register16::HL);
+    assert_eq!(Bits::Swap(Operand::Register(Register8::B)).cycles(), 8);
+    assert_eq!(Bits::Swap(hl).cycles(), 16);
+    assert_eq!(Bits::GetBit(7, hl).cycles(), 12);
+    assert_eq!(Bits::SetBit(7, hl).cycles(), 16);
+}
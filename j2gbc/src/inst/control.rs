@@ -20,6 +20,13 @@ pub enum Control {
 }
 
 impl Control {
+    pub fn is_call(self) -> bool {
+        matches!(
+            self,
+            Control::Call(_) | Control::CallConditional(_, _) | Control::Reset(_)
+        )
+    }
+
     pub fn cycles(self, branch_taken: bool) -> u8 {
         match self {
             Control::Reset(_) => 16,
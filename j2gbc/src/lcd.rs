@@ -1,21 +1,27 @@
 use std::cmp::max;
+use std::collections::HashSet;
 use std::num::Wrapping;
 
 use j2ds::{next_timer_event, Timer, TimerEvent};
-use log::error;
 
 use crate::error::ExecutionError;
 use crate::{
     cpu::{Interrupt, InterruptSet, CLOCK_RATE},
-    mem::{Address, MemDevice, Ram, RNG_CHAR_DAT, RNG_LCD_BGDD1, RNG_LCD_BGDD2, RNG_LCD_OAM},
+    mem::{
+        Address, MemDevice, Ram, RamFill, RNG_CHAR_DAT, RNG_LCD_BGDD1, RNG_LCD_BGDD2, RNG_LCD_OAM,
+    },
+    rng::Rng,
     system::SystemMode,
 };
 
 mod bg;
+mod capture;
+mod cvd;
 pub mod fb;
 mod obj;
 mod scanline;
 mod tile;
+mod transform;
 
 const REG_LCDC: Address = Address(0xFF40);
 const REG_STAT: Address = Address(0xFF41);
@@ -42,7 +48,7 @@ const LINE_CYCLE_TIME: u64 = CLOCK_RATE * 108_700 / 1_000_000_000; // Src: Offic
 const HBLANK_DURATION: u64 = CLOCK_RATE * 48_600 / 1_000_000_000; // Src: GBCPUMan.pdf
 const MODE_10_DURATION: u64 = CLOCK_RATE * 19_000 / 1_000_000_000; // Src: GBCPUMan.pdf
 const VBLANK_DURATION: u64 = LINE_CYCLE_TIME * 10; // Src: Official GB manual
-const SCREEN_CYCLE_TIME: u64 = TOTAL_SCANLINES * LINE_CYCLE_TIME;
+pub const SCREEN_CYCLE_TIME: u64 = TOTAL_SCANLINES * LINE_CYCLE_TIME;
 const BYTES_PER_CHAR: u16 = 16;
 const BYTES_PER_ROW: u16 = 2;
 const BG_CHARS_PER_ROW: u8 = 32;
@@ -56,7 +62,7 @@ const MODE_00_INT_FLAG: u8 = 0b0000_1000;
 const MODE_00_MASK: u8 = 0b00;
 const MODE_01_MASK: u8 = 0b01;
 const MODE_10_MASK: u8 = 0b10;
-const _MODE_11_MASK: u8 = 0b11;
+const MODE_11_MASK: u8 = 0b11;
 
 const LYC_MATCH_FLAG: u8 = 0b0000_0100;
 const BG_ENABLED_FLAG: u8 = 0b0000_0001;
@@ -69,14 +75,28 @@ const BGD_CODE_DAT_FLAG: u8 = 0b0000_1000;
 const WINDOW_CODE_DAT_FLAG: u8 = 0b0100_0000;
 
 const TILE_COUNT: usize = 384 * 2;
+const TILES_PER_BANK: usize = TILE_COUNT / 2;
+const TILE_GRID_WIDTH: usize = 16;
 const OBJ_COUNT: usize = 40;
 
 const PAL_DATA_IDX: u8 = 0b11_1111;
 
 pub const BG_SIZE: (usize, usize) = (255, 255);
 
+pub use self::cvd::CvdType;
+pub use self::transform::Rotation;
+
+pub type BgBuffer = fb::Framebuffer;
+pub type FrameRow = [fb::Pixel; fb::SCREEN_SIZE.0];
+
 type CgbPalette = [fb::Pixel; 4];
 
+// Tile data bytes and OAM written while cache updates are deferred
+struct BulkVram {
+    tiles: Option<(u16, u16)>,
+    objs: bool,
+}
+
 pub struct Lcd {
     lcdc: u8,
     stat: u8,
@@ -87,6 +107,12 @@ pub struct Lcd {
     wy: u8,
     sx: u8,
     sy: u8,
+    // Scroll and palette registers as latched when the current line started drawing
+    line_sx: u8,
+    line_sy: u8,
+    line_bgp: u8,
+    // The line WY matched LY on, once the window has been triggered this frame
+    window_start_line: Option<u8>,
     bcps: u8,
     ocps: u8,
     bank_select: usize,
@@ -107,11 +133,23 @@ pub struct Lcd {
     hblank_timer: Timer,
     vblank_timer: Timer,
     mode10_timer: Timer,
+    // The mode timers count from the cycle the LCD was last switched on
+    timer_offset: u64,
     scanline_sweeper: scanline::ScanlineSweeper,
+    // Set when the LCD is switched back on; the timers restart on the next pump
+    restart_pending: bool,
+    bulk_vram: Option<BulkVram>,
 
     running_until_cycle: u64,
+    force_render: bool,
+    cvd_filter: Option<CvdType>,
+    bg_color0_transparent: bool,
+    output_transform: transform::OutputTransform,
+    frame_count: u64,
+    capture: Option<capture::FrameCapture>,
 
     tiles: [tile::MonoTile; TILE_COUNT],
+    dirty_tiles: HashSet<usize>,
     objs: [obj::Obj; OBJ_COUNT],
 
     system_mode: SystemMode,
@@ -129,6 +167,10 @@ impl Lcd {
             wy: 0,
             sx: 0,
             sy: 0,
+            line_sx: 0,
+            line_sy: 0,
+            line_bgp: 0,
+            window_start_line: None,
             bcps: 0,
             ocps: 0,
             bank_select: 0,
@@ -148,26 +190,24 @@ impl Lcd {
             obj_palettes: [[fb::DMG_COLOR_WHITE; 4]; 8],
             bg_palettes: [[fb::DMG_COLOR_WHITE; 4]; 8],
 
-            hblank_timer: Timer::new(
-                LINE_CYCLE_TIME,
-                LINE_CYCLE_TIME - HBLANK_DURATION - MODE_10_DURATION,
-                HBLANK_DURATION,
-            ),
-            vblank_timer: Timer::new(
-                SCREEN_CYCLE_TIME,
-                fb::SCREEN_SIZE.1 as u64 * LINE_CYCLE_TIME,
-                VBLANK_DURATION,
-            ),
-            mode10_timer: Timer::new(
-                LINE_CYCLE_TIME,
-                LINE_CYCLE_TIME - HBLANK_DURATION,
-                HBLANK_DURATION,
-            ),
+            hblank_timer: hblank_timer(),
+            vblank_timer: vblank_timer(),
+            mode10_timer: mode10_timer(),
+            timer_offset: 0,
+            restart_pending: false,
+            bulk_vram: None,
             running_until_cycle: 0,
+            force_render: false,
+            cvd_filter: None,
+            bg_color0_transparent: false,
+            output_transform: transform::OutputTransform::default(),
+            frame_count: 0,
+            capture: None,
 
             scanline_sweeper: scanline::ScanlineSweeper::new(),
 
             tiles: [tile::MonoTile::default(); TILE_COUNT],
+            dirty_tiles: HashSet::new(),
             objs: [obj::Obj::default(); OBJ_COUNT],
 
             system_mode: if cgb_mode {
@@ -178,6 +218,50 @@ impl Lcd {
         }
     }
 
+    pub fn fill_ram(&mut self, fill: RamFill, rng: &mut Rng) {
+        self.cdata.fill(fill, rng);
+        self.bgdd1.fill(fill, rng);
+        self.bgdd2.fill(fill, rng);
+        self.oam.fill(fill, rng);
+
+        for i in (0..self.cdata.data.len()).step_by(2) {
+            self.update_tile_at(RNG_CHAR_DAT.0 + Address(i as u16));
+        }
+        for i in 0..OBJ_COUNT {
+            self.objs[i] = self.read_obj(i as u8);
+        }
+    }
+
+    pub fn begin_bulk_vram(&mut self) {
+        if self.bulk_vram.is_none() {
+            self.bulk_vram = Some(BulkVram {
+                tiles: None,
+                objs: false,
+            });
+        }
+    }
+
+    pub fn end_bulk_vram(&mut self) {
+        if let Some(bulk) = self.bulk_vram.take() {
+            if let Some((lo, hi)) = bulk.tiles {
+                for offset in (lo - lo % 2..=hi).step_by(2) {
+                    self.update_tile_at(RNG_CHAR_DAT.0 + Address(offset));
+                }
+            }
+            if bulk.objs {
+                for i in 0..OBJ_COUNT {
+                    self.objs[i] = self.read_obj(i as u8);
+                }
+            }
+        }
+    }
+
+    pub fn dirty_tiles_since_last_frame(&mut self) -> Vec<usize> {
+        let mut tiles: Vec<usize> = self.dirty_tiles.drain().collect();
+        tiles.sort_unstable();
+        tiles
+    }
+
     pub fn get_framebuffer(&self) -> &fb::Framebuffer {
         &self.fbs[self.fbi]
     }
@@ -199,28 +283,78 @@ impl Lcd {
     }
 
     pub fn get_next_event_cycle(&self) -> u64 {
-        next_timer_event(&[
-            self.hblank_timer,
-            self.vblank_timer,
-            self.mode10_timer,
-            self.scanline_sweeper.timer(),
-        ])
+        let mode_event = self.timer_offset
+            + next_timer_event(&[self.hblank_timer, self.vblank_timer, self.mode10_timer]);
+        mode_event.min(self.scanline_sweeper.get_next_event_cycle())
     }
 
     pub fn set_running_until(&mut self, cycle: u64) {
         self.running_until_cycle = cycle;
     }
 
+    pub fn set_output_transform(&mut self, rotation: Rotation, flip_h: bool, flip_v: bool) {
+        self.output_transform = transform::OutputTransform {
+            rotation,
+            flip_h,
+            flip_v,
+        };
+    }
+
+    pub fn output_size(&self) -> (usize, usize) {
+        self.output_transform.size(fb::SCREEN_SIZE)
+    }
+
+    pub fn render_transformed(&self) -> Vec<fb::Pixel> {
+        self.output_transform.apply(self.get_framebuffer())
+    }
+
+    pub fn set_cvd_filter(&mut self, filter: Option<CvdType>) {
+        self.cvd_filter = filter;
+    }
+
+    pub fn set_force_render(&mut self, force: bool) {
+        self.force_render = force;
+    }
+
+    pub fn set_bg_color0_transparent(&mut self, transparent: bool) {
+        self.bg_color0_transparent = transparent;
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.scanline_sweeper.ly()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn start_capture(&mut self, frame_skip: usize, max_frames: usize) {
+        self.capture = Some(capture::FrameCapture::new(frame_skip, max_frames));
+    }
+
+    pub fn stop_capture(&mut self) -> Vec<Vec<u8>> {
+        self.capture
+            .take()
+            .map(capture::FrameCapture::into_frames)
+            .unwrap_or_default()
+    }
+
     pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
         let mut inters = InterruptSet::default();
 
+        if self.restart_pending {
+            self.restart_pending = false;
+            self.restart_timers(cycle);
+        }
+
         let scanline_inter = self.scanline_sweeper.pump_cycle(cycle);
         self.stat = (self.stat & !LYC_MATCH_FLAG) | self.scanline_sweeper.stat_flags();
         if let Some(intr) = scanline_inter {
             inters.add_interrupt(intr);
         }
 
-        match self.hblank_timer.update(cycle) {
+        let timer_cycle = cycle - self.timer_offset;
+        match self.hblank_timer.update(timer_cycle) {
             Some(TimerEvent::RisingEdge) => {
                 if self.scanline_sweeper.on_visible_scanline() {
                     self.do_hblank_start(cycle);
@@ -235,32 +369,59 @@ impl Lcd {
             None => {}
         }
 
-        match self.mode10_timer.update(cycle) {
+        // VBlank ends on the same cycle line 0 starts, so handle it before mode 10 begins
+        match self.vblank_timer.update(timer_cycle) {
             Some(TimerEvent::RisingEdge) => {
+                self.do_vblank_start();
+                inters.add_interrupt(Interrupt::VBlank);
+            }
+            Some(TimerEvent::FallingEdge) => {
+                self.do_vblank_end();
+            }
+            None => {}
+        }
+
+        match self.mode10_timer.update(timer_cycle) {
+            Some(TimerEvent::RisingEdge) if self.scanline_sweeper.on_visible_scanline() => {
                 self.stat = (self.stat & 0b1111_1100) | MODE_10_MASK;
 
                 if self.is_mode_10_int_enabled() {
                     inters.add_interrupt(Interrupt::LCDC);
                 }
             }
-            Some(TimerEvent::FallingEdge) => {
-                self.stat = (self.stat & 0b1111_1100) | MODE_00_MASK;
+            Some(TimerEvent::FallingEdge) if self.scanline_sweeper.on_visible_scanline() => {
+                self.stat = (self.stat & 0b1111_1100) | MODE_11_MASK;
+                self.latch_line_registers();
             }
-            None => {}
+            _ => {}
         }
 
-        match self.vblank_timer.update(cycle) {
-            Some(TimerEvent::RisingEdge) => {
-                self.do_vblank_start();
-                inters.add_interrupt(Interrupt::VBlank);
-            }
-            Some(TimerEvent::FallingEdge) => {
-                self.do_vblank_end();
-            }
-            None => {}
+        // The timers keep running while the LCD is off, but nothing reaches the CPU
+        if self.is_lcd_enabled() {
+            inters
+        } else {
+            InterruptSet::default()
         }
+    }
+
+    fn restart_timers(&mut self, cycle: u64) {
+        self.hblank_timer = hblank_timer();
+        self.vblank_timer = vblank_timer();
+        self.mode10_timer = mode10_timer();
+        self.timer_offset = cycle;
+        self.scanline_sweeper.restart(cycle);
+        self.window_start_line = None;
+    }
+
+    fn latch_line_registers(&mut self) {
+        self.line_sx = self.sx;
+        self.line_sy = self.sy;
+        self.line_bgp = self.bgp;
 
-        inters
+        let ly = self.scanline_sweeper.ly();
+        if self.window_start_line.is_none() && self.wy == ly {
+            self.window_start_line = Some(ly);
+        }
     }
 
     fn do_hblank_start(&mut self, cycle: u64) {
@@ -271,14 +432,20 @@ impl Lcd {
     }
 
     fn should_render_this_frame(&self, cycle: u64) -> bool {
-        cycle >= self.running_until_cycle
+        self.force_render
+            || cycle >= self.running_until_cycle
             || self.running_until_cycle - cycle <= 2 * SCREEN_CYCLE_TIME
     }
 
     fn do_hblank_end(&mut self) {}
 
     pub fn do_vblank_start(&mut self) {
+        self.window_start_line = None;
         self.swap();
+        self.frame_count += 1;
+        if let Some(capture) = &mut self.capture {
+            capture.record(&self.fbs[self.fbi]);
+        }
         self.stat = (self.stat & 0b1111_1100) | MODE_01_MASK;
     }
 
@@ -287,64 +454,100 @@ impl Lcd {
     }
 
     fn render_screen_row(&mut self) {
-        let y = self.scanline_sweeper.ly() as usize;
-        if !self.is_lcd_enabled() {
-            for x in 0..(fb::SCREEN_SIZE.0 as usize) {
-                self.get_back_framebuffer().set(x, y, fb::DMG_COLOR_WHITE);
+        let y = self.scanline_sweeper.ly();
+        let (row, bg_color0) = self.compose_scanline(y);
+        let transparent = self.bg_color0_transparent;
+        let fb = self.get_back_framebuffer();
+        for (x, color) in row.iter().enumerate() {
+            fb.set(x, y as usize, *color);
+            if transparent && bg_color0[x] {
+                fb.set_transparent(x, y as usize);
             }
-            return;
+        }
+    }
+
+    // Composes one line from the current state without touching timers or framebuffers
+    pub fn render_scanline(&self, ly: u8) -> FrameRow {
+        self.compose_scanline(ly).0
+    }
+
+    // Also reports which pixels show background color 0 with no object on top
+    fn compose_scanline(&self, ly: u8) -> (FrameRow, [bool; fb::SCREEN_SIZE.0]) {
+        let mut bg_color0 = [false; fb::SCREEN_SIZE.0];
+        if !self.is_lcd_enabled() {
+            return (
+                [self.filter_color(fb::DMG_COLOR_WHITE); fb::SCREEN_SIZE.0],
+                bg_color0,
+            );
         }
 
         let mut bg_screen_row =
             [fb::TentativePixel::new(fb::DMG_COLOR_WHITE, false, true); fb::SCREEN_SIZE.0];
         let mut oam_screen_row = [None; fb::SCREEN_SIZE.0];
-        self.render_background_row(&mut bg_screen_row);
-        self.render_window_row(&mut bg_screen_row);
-        self.render_oam_row(&mut oam_screen_row);
-
-        for x in 0..(fb::SCREEN_SIZE.0 as usize) {
-            let color = fb::resolve_pixel(self.system_mode, oam_screen_row[x], bg_screen_row[x]);
+        self.render_background_row(ly, &mut bg_screen_row);
+        self.render_window_row(ly, &mut bg_screen_row);
+        self.render_oam_row(ly, &mut oam_screen_row);
+
+        let mut row = [fb::DMG_COLOR_WHITE; fb::SCREEN_SIZE.0];
+        for (x, color) in row.iter_mut().enumerate() {
+            let resolved = fb::resolve_pixel(self.system_mode, oam_screen_row[x], bg_screen_row[x]);
+            *color = self.filter_color(resolved);
+            bg_color0[x] = oam_screen_row[x].is_none() && bg_screen_row[x].data_was_zero();
+        }
+        (row, bg_color0)
+    }
 
-            self.get_back_framebuffer().set(x, y, color);
+    fn filter_color(&self, color: fb::Pixel) -> fb::Pixel {
+        match self.cvd_filter {
+            Some(filter) => filter.apply(color),
+            None => color,
         }
     }
 
-    fn render_background_row(&self, screen_row: &mut [fb::TentativePixel]) {
+    fn render_background_row(&self, ly: u8, screen_row: &mut [fb::TentativePixel]) {
         if !self.is_bg_enabled() {
             return;
         }
         self.render_tile_row(
-            self.scanline_sweeper.ly(),
-            self.sx,
-            self.sy,
+            ly,
+            self.line_sx,
+            self.line_sy,
             0,
             self.get_bg_code_dat_start(),
+            self.get_bg_char_addr_start(),
+            self.line_bgp,
             screen_row,
         );
     }
 
-    fn render_window_row(&self, screen_row: &mut [fb::TentativePixel]) {
+    fn render_window_row(&self, ly: u8, screen_row: &mut [fb::TentativePixel]) {
         if !self.is_window_enabled() {
             return;
         }
 
         let adjusted_wx = max(self.wx, 7) - 7;
-        let ly = self.scanline_sweeper.ly();
-        if self.wy > ly || adjusted_wx >= fb::SCREEN_SIZE.0 as u8 {
+        let start_line = match self.window_start_line {
+            Some(start_line) if start_line <= ly => start_line,
+            _ => return,
+        };
+        if adjusted_wx >= fb::SCREEN_SIZE.0 as u8 {
             return;
         }
 
-        let translated_y = ly - self.wy;
+        let translated_y = ly - start_line;
         self.render_tile_row(
             translated_y,
             0,
             0,
             if self.wx > 7 { self.wx - 7 } else { 0 },
             self.get_window_code_dat_start(),
+            self.get_bg_char_addr_start(),
+            self.line_bgp,
             screen_row,
         );
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn render_tile_row(
         &self,
         screen_y: u8,
@@ -352,6 +555,8 @@ impl Lcd {
         scy: u8,
         start_x: u8,
         code_dat_start: Address,
+        signed: bool,
+        bgp: u8,
         screen_row: &mut [fb::TentativePixel],
     ) {
         let translated_y = Wrapping(screen_y) + Wrapping(scy); // Implicit % 256
@@ -387,28 +592,20 @@ impl Lcd {
                 translated_y % Wrapping(8)
             };
 
-            let signed = self.get_bg_char_addr_start();
             let char_row = self.read_char_row_at(char_, maybe_flipped_y.0, signed, flags.bank());
 
-            let (color, data) = match self.system_mode {
-                SystemMode::CGB => {
-                    let maybe_flipped_x = if flags.xflip() {
-                        Wrapping(7) - (translated_x % Wrapping(8))
-                    } else {
-                        translated_x % Wrapping(8)
-                    };
-                    let color_index = char_row[maybe_flipped_x.0 as usize];
-                    (
-                        self.bg_palettes[flags.cgb_pallete() as usize][color_index as usize],
-                        color_index,
-                    )
-                }
-                SystemMode::DMG => {
-                    let color_index = char_row[(translated_x % Wrapping(8)).0 as usize];
-                    let corrected_index = palette_convert(color_index, self.bgp) as usize;
-                    (fb::DMG_COLORS[corrected_index], color_index)
-                }
+            let maybe_flipped_x = if flags.xflip() {
+                Wrapping(7) - (translated_x % Wrapping(8))
+            } else {
+                translated_x % Wrapping(8)
             };
+            let data = char_row[maybe_flipped_x.0 as usize];
+            let color = resolve_palette(
+                self.system_mode,
+                data,
+                bgp,
+                &self.bg_palettes[flags.cgb_pallete() as usize],
+            );
 
             screen_row[screen_x as usize] =
                 fb::TentativePixel::new(color, flags.priority(), data == 0);
@@ -416,11 +613,7 @@ impl Lcd {
     }
 
     fn read_char_row_at(&self, char_: u8, row: u8, signed: bool, bank: u8) -> tile::MonoTileRow {
-        let index = if signed {
-            (256 + isize::from(char_ as i8)) as usize
-        } else {
-            char_ as usize
-        } + (bank as usize) * (256 + 128);
+        let index = resolve_tile_index(char_, signed) + (bank as usize) * (256 + 128);
 
         if row >= 8 {
             self.tiles[index + 1].read_row(row as usize - 8)
@@ -429,16 +622,94 @@ impl Lcd {
         }
     }
 
-    pub fn render_bg_to_fb(&self, index: usize, output: &mut fb::Framebuffer) {
-        let tile_address = if index == 0 { BG_START_1 } else { BG_START_2 };
+    pub fn render_bg_map(&self, first: bool, signed: bool) -> Box<BgBuffer> {
+        let mut output = Box::new(BgBuffer::new(BG_SIZE));
+        let tile_address = if first { BG_START_1 } else { BG_START_2 };
+        self.render_bg_map_into(tile_address, signed, &mut output);
+        output
+    }
+
+    pub fn dump_tilemap(&self, first: bool) -> [[u8; 32]; 32] {
+        let map = if first { &self.bgdd1 } else { &self.bgdd2 };
+        let mut output = [[0; 32]; 32];
+        for (y, row) in output.iter_mut().enumerate() {
+            for (x, char_) in row.iter_mut().enumerate() {
+                *char_ = map.data[y * BG_CHARS_PER_ROW as usize + x];
+            }
+        }
+        output
+    }
+
+    // Tile index a raw background map entry refers to under the current LCDC tile data mode
+    pub fn bg_tile_index(&self, char_: u8) -> usize {
+        resolve_tile_index(char_, self.get_bg_char_addr_start())
+    }
+
+    pub fn render_both_bg_maps(&self) -> (Box<BgBuffer>, Box<BgBuffer>) {
+        let signed = self.get_bg_char_addr_start();
+        (
+            self.render_bg_map(true, signed),
+            self.render_bg_map(false, signed),
+        )
+    }
+
+    fn render_bg_map_into(&self, tile_address: Address, signed: bool, output: &mut BgBuffer) {
         for y in 0..BG_SIZE.1 {
             let mut bg_screen_row =
                 [fb::TentativePixel::new(fb::DMG_COLOR_WHITE, false, true); BG_SIZE.0];
-            self.render_tile_row(y as u8, 0, 0, 0, tile_address, &mut bg_screen_row);
+            self.render_tile_row(
+                y as u8,
+                0,
+                0,
+                0,
+                tile_address,
+                signed,
+                self.bgp,
+                &mut bg_screen_row,
+            );
             for (x, pixel) in bg_screen_row.iter().enumerate() {
                 output.set(x, y, pixel.color());
             }
         }
+    }
+
+    // The background and object palettes as currently resolved. In DMG mode these are
+    // just BGP, then OBP0 and OBP1.
+    pub fn all_palettes(&self) -> (Vec<[fb::Pixel; 4]>, Vec<[fb::Pixel; 4]>) {
+        match self.system_mode {
+            SystemMode::CGB => (self.bg_palettes.to_vec(), self.obj_palettes.to_vec()),
+            SystemMode::DMG => {
+                let resolve =
+                    |p| [0, 1, 2, 3].map(|v| fb::DMG_COLORS[palette_convert(v, p) as usize]);
+                (
+                    vec![resolve(self.bgp)],
+                    vec![resolve(self.obp0), resolve(self.obp1)],
+                )
+            }
+        }
+    }
+
+    pub fn render_all_tiles(&self, palette: [fb::Pixel; 4]) -> (Vec<fb::Pixel>, (usize, usize)) {
+        let width = TILE_GRID_WIDTH * PIXEL_PER_CHAR as usize;
+        let height = TILES_PER_BANK / TILE_GRID_WIDTH * PIXEL_PER_CHAR as usize;
+        let mut output = vec![palette[0]; width * height];
+
+        for (i, tile) in self.tiles[..TILES_PER_BANK].iter().enumerate() {
+            let base_x = i % TILE_GRID_WIDTH * PIXEL_PER_CHAR as usize;
+            let base_y = i / TILE_GRID_WIDTH * PIXEL_PER_CHAR as usize;
+            for y in 0..PIXEL_PER_CHAR as usize {
+                for (x, color_index) in tile.read_row(y).iter().enumerate() {
+                    output[base_x + x + (base_y + y) * width] = palette[*color_index as usize];
+                }
+            }
+        }
+
+        (output, (width, height))
+    }
+
+    pub fn render_bg_to_fb(&self, index: usize, output: &mut fb::Framebuffer) {
+        let tile_address = if index == 0 { BG_START_1 } else { BG_START_2 };
+        self.render_bg_map_into(tile_address, self.get_bg_char_addr_start(), output);
 
         if tile_address == self.get_bg_code_dat_start() {
             output.draw_wrapping_rect(
@@ -521,7 +792,7 @@ impl Lcd {
         )
     }
 
-    fn render_oam_row(&self, screen_row: &mut [Option<fb::TentativePixel>]) {
+    fn render_oam_row(&self, ly: u8, screen_row: &mut [Option<fb::TentativePixel>]) {
         if !self.is_oam_enabled() {
             return;
         }
@@ -537,10 +808,7 @@ impl Lcd {
 
             for y in 0..hi_y {
                 let full_y = y as isize + obj.y as isize - 16;
-                if full_y > fb::SCREEN_SIZE.1 as isize
-                    || full_y < 0
-                    || full_y != self.scanline_sweeper.ly() as isize
-                {
+                if full_y > fb::SCREEN_SIZE.1 as isize || full_y < 0 || full_y != ly as isize {
                     continue;
                 }
 
@@ -559,20 +827,17 @@ impl Lcd {
                         // 0 is always transparent
                         continue;
                     }
-                    let color = match self.system_mode {
-                        SystemMode::CGB => {
-                            self.obj_palettes[obj.cgb_palette() as usize][color_index as usize]
-                        }
-                        SystemMode::DMG => {
-                            let pal = if obj.high_palette() {
-                                self.obp1
-                            } else {
-                                self.obp0
-                            };
-                            let corrected_index = palette_convert(color_index, pal) as usize;
-                            fb::DMG_COLORS[corrected_index]
-                        }
+                    let pal = if obj.high_palette() {
+                        self.obp1
+                    } else {
+                        self.obp0
                     };
+                    let color = resolve_palette(
+                        self.system_mode,
+                        color_index,
+                        pal,
+                        &self.obj_palettes[obj.cgb_palette() as usize],
+                    );
 
                     screen_row[full_x as usize] = Some(fb::TentativePixel::new(
                         color,
@@ -592,6 +857,7 @@ impl Lcd {
         let b1 = self.cdata.read(byte_offset).unwrap();
         let b2 = self.cdata.read(byte_offset + Address(1)).unwrap();
         self.tiles[char_offset as usize].update_row(row_offset as usize, b1, b2);
+        self.dirty_tiles.insert(char_offset as usize);
     }
 
     fn update_obj_at(&mut self, a: Address) {
@@ -623,10 +889,73 @@ fn load_color_from_data(data: &[u8], pal_out: &mut [CgbPalette]) {
     }
 }
 
+fn resolve_tile_index(char_: u8, signed: bool) -> usize {
+    if signed {
+        (256 + isize::from(char_ as i8)) as usize
+    } else {
+        char_ as usize
+    }
+}
+
 fn palette_convert(v: u8, p: u8) -> u8 {
     (p >> (v * 2)) & 0b11
 }
 
+// In CGB mode the DMG palette registers are ignored in favour of the CGB palette memory
+fn resolve_palette(
+    mode: SystemMode,
+    v: u8,
+    dmg_palette: u8,
+    cgb_palette: &CgbPalette,
+) -> fb::Pixel {
+    match mode {
+        SystemMode::CGB => cgb_palette[v as usize],
+        SystemMode::DMG => fb::DMG_COLORS[palette_convert(v, dmg_palette) as usize],
+    }
+}
+
+fn hblank_timer() -> Timer {
+    Timer::new(
+        LINE_CYCLE_TIME,
+        LINE_CYCLE_TIME - HBLANK_DURATION - MODE_10_DURATION,
+        HBLANK_DURATION,
+    )
+}
+
+fn vblank_timer() -> Timer {
+    Timer::new(
+        SCREEN_CYCLE_TIME,
+        fb::SCREEN_SIZE.1 as u64 * LINE_CYCLE_TIME,
+        VBLANK_DURATION,
+    )
+}
+
+fn mode10_timer() -> Timer {
+    Timer::new(LINE_CYCLE_TIME, 0, MODE_10_DURATION)
+}
+
+// Framebuffers of different sizes count as differing at every pixel of the larger one
+pub fn framebuffer_diff_count(a: &fb::Framebuffer, b: &fb::Framebuffer) -> usize {
+    if a.size() != b.size() {
+        return a.raw().len().max(b.raw().len());
+    }
+    a.raw().iter().zip(b.raw()).filter(|(x, y)| x != y).count()
+}
+
+// Returns the (x, y) of the first mismatching pixel in raster order, which is (0, 0) when the
+// sizes differ
+pub fn first_difference(a: &fb::Framebuffer, b: &fb::Framebuffer) -> Option<(usize, usize)> {
+    if a.size() != b.size() {
+        return Some((0, 0));
+    }
+    let width = a.size().0;
+    a.raw()
+        .iter()
+        .zip(b.raw())
+        .position(|(x, y)| x != y)
+        .map(|i| (i % width, i / width))
+}
+
 #[test]
 fn test_palette_convert() {
     assert_eq!(0b11, palette_convert(0, 0b11));
@@ -634,6 +963,363 @@ fn test_palette_convert() {
     assert_eq!(0b01, palette_convert(1, 0b0100));
 }
 
+#[test]
+fn test_palette_mode() {
+    let render = |cgb_mode| {
+        let mut lcd = Lcd::new(cgb_mode);
+        lcd.write(
+            REG_LCDC,
+            LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+        )
+        .unwrap();
+        lcd.write(REG_BGP, 0x1B).unwrap();
+        lcd.write(REG_BCPS, 0x80).unwrap();
+        lcd.write(REG_BCPD, 0x1F).unwrap();
+        lcd.write(REG_BCPD, 0x00).unwrap();
+        lcd.latch_line_registers();
+        lcd.render_screen_row();
+        lcd.get_back_framebuffer().get(0, 0)
+    };
+
+    assert_eq!(render(false), fb::DMG_COLOR_BLACK);
+    assert_eq!(render(true), [255, 0, 0]);
+}
+
+#[test]
+fn test_scroll_latched_per_line() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0xE4).unwrap();
+    lcd.write(Address(0x9801), 1).unwrap();
+    for a in 0x8010..0x8020 {
+        lcd.write(Address(a), 0xFF).unwrap();
+    }
+
+    for cycle in (0..LINE_CYCLE_TIME * 3).step_by(4) {
+        lcd.pump_cycle(cycle);
+        let mode = lcd.stat & 0b11;
+        match (lcd.scanline_sweeper.ly(), mode, lcd.sx) {
+            // Written during H-blank, so it should apply to the next line
+            (0, MODE_00_MASK, 0) => lcd.write(REG_SCX, 8).unwrap(),
+            // Written while the line is drawing, so it should wait for the line after
+            (1, MODE_11_MASK, 8) => lcd.write(REG_SCX, 16).unwrap(),
+            _ => {}
+        }
+    }
+
+    let fb = lcd.get_back_framebuffer();
+    assert_eq!(fb.get(0, 0), fb::DMG_COLOR_WHITE);
+    assert_eq!(fb.get(8, 0), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(0, 1), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(0, 2), fb::DMG_COLOR_WHITE);
+}
+
+#[test]
+fn test_bgp_latched_per_line() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0xE4).unwrap();
+
+    for cycle in (0..LINE_CYCLE_TIME * 3).step_by(4) {
+        lcd.pump_cycle(cycle);
+        let mode = lcd.stat & 0b11;
+        match (lcd.scanline_sweeper.ly(), mode, lcd.bgp) {
+            (0, MODE_00_MASK, 0xE4) => lcd.write(REG_BGP, 0xE7).unwrap(),
+            (1, MODE_11_MASK, 0xE7) => lcd.write(REG_BGP, 0xE5).unwrap(),
+            _ => {}
+        }
+    }
+
+    let fb = lcd.get_back_framebuffer();
+    assert_eq!(fb.get(0, 0), fb::DMG_COLOR_WHITE);
+    assert_eq!(fb.get(0, 1), fb::DMG_COLOR_BLACK);
+    assert_eq!(fb.get(0, 2), fb::DMG_COLOR_LIGHT_GRAY);
+}
+
+#[test]
+fn test_window_triggered_by_wy_match() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG
+            | BGD_CHAR_DAT_FLAG
+            | BG_ENABLED_FLAG
+            | WINDOW_ENABLED_FLAG
+            | WINDOW_CODE_DAT_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0xE4).unwrap();
+    lcd.write(REG_WX, 7).unwrap();
+    lcd.write(REG_WY, 100).unwrap();
+    for a in 0x9C00..0xA000 {
+        lcd.write(Address(a), 1).unwrap();
+    }
+    for a in 0x8010..0x8020 {
+        lcd.write(Address(a), 0xFF).unwrap();
+    }
+
+    for frame in 0..2 {
+        let start = frame * SCREEN_CYCLE_TIME;
+        for cycle in (start..start + SCREEN_CYCLE_TIME).step_by(4) {
+            lcd.pump_cycle(cycle);
+            match (frame, lcd.scanline_sweeper.ly(), lcd.wy) {
+                // Lowered past LY, so the window has to wait for the next frame
+                (0, 20, 100) => lcd.write(REG_WY, 10).unwrap(),
+                // Already triggered, so the window keeps drawing
+                (1, 30, 10) => lcd.write(REG_WY, 100).unwrap(),
+                _ => {}
+            }
+        }
+
+        let fb = lcd.get_framebuffer();
+        if frame == 0 {
+            assert_eq!(fb.get(0, 10), fb::DMG_COLOR_WHITE);
+            assert_eq!(fb.get(0, 50), fb::DMG_COLOR_WHITE);
+        } else {
+            assert_eq!(fb.get(0, 9), fb::DMG_COLOR_WHITE);
+            assert_eq!(fb.get(0, 10), fb::DMG_COLOR_BLACK);
+            assert_eq!(fb.get(0, 50), fb::DMG_COLOR_BLACK);
+        }
+    }
+}
+
+#[test]
+fn test_force_render() {
+    let render_first_frame = |force| {
+        let mut lcd = Lcd::new(false);
+        lcd.write(
+            REG_LCDC,
+            LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+        )
+        .unwrap();
+        lcd.write(REG_BGP, 0xFF).unwrap();
+        lcd.set_force_render(force);
+        lcd.set_running_until(10 * SCREEN_CYCLE_TIME);
+
+        for cycle in (0..fb::SCREEN_SIZE.1 as u64 * LINE_CYCLE_TIME).step_by(4) {
+            lcd.pump_cycle(cycle);
+        }
+        lcd.get_back_framebuffer().get(0, fb::SCREEN_SIZE.1 - 1)
+    };
+
+    assert_eq!(render_first_frame(false), fb::DMG_COLOR_WHITE);
+    assert_eq!(render_first_frame(true), fb::DMG_COLOR_BLACK);
+}
+
+#[test]
+fn test_cvd_filter() {
+    let mut lcd = Lcd::new(true);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BCPS, 0x80).unwrap();
+    lcd.write(REG_BCPD, 0xE0).unwrap();
+    lcd.write(REG_BCPD, 0x03).unwrap();
+
+    lcd.render_screen_row();
+    assert_eq!(lcd.get_back_framebuffer().get(0, 0), [0, 255, 0]);
+
+    lcd.set_cvd_filter(Some(CvdType::Deuteranopia));
+    lcd.render_screen_row();
+    assert_eq!(lcd.get_back_framebuffer().get(0, 0), [96, 77, 77]);
+}
+
+#[test]
+fn test_render_scanline_matches_frame() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0xE4).unwrap();
+    lcd.write(REG_SCX, 3).unwrap();
+    lcd.write(Address(0x9821), 1).unwrap();
+    for a in 0x8010..0x8020 {
+        lcd.write(Address(a), if a % 2 == 0 { 0xF0 } else { 0x3C })
+            .unwrap();
+    }
+
+    for cycle in (0..SCREEN_CYCLE_TIME).step_by(4) {
+        lcd.pump_cycle(cycle);
+    }
+
+    let row = lcd.render_scanline(10);
+    assert!(row.iter().any(|p| *p != fb::DMG_COLOR_WHITE));
+    for (x, color) in row.iter().enumerate() {
+        assert_eq!(*color, lcd.get_framebuffer().get(x, 10));
+    }
+}
+
+#[test]
+fn test_mode_10_interrupt_once_per_visible_line() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(REG_STAT, MODE_10_INT_FLAG).unwrap();
+
+    for frame in 0..2 {
+        let mut count = 0;
+        let start = frame * SCREEN_CYCLE_TIME;
+        for cycle in (start..start + SCREEN_CYCLE_TIME).step_by(4) {
+            if lcd.pump_cycle(cycle).if_() & Interrupt::LCDC.bits() != 0 {
+                count += 1;
+            }
+        }
+        assert_eq!(count, fb::SCREEN_SIZE.1);
+    }
+}
+
+#[test]
+fn test_vblank_after_lcd_reenable() {
+    let mut lcd = Lcd::new(false);
+    let mut cycle = 0;
+    while lcd.ly() != 70 {
+        cycle += 4;
+        lcd.pump_cycle(cycle);
+    }
+
+    lcd.write(REG_LCDC, BG_ENABLED_FLAG).unwrap();
+    for _ in 0..SCREEN_CYCLE_TIME / 4 {
+        cycle += 4;
+        assert_eq!(lcd.pump_cycle(cycle).if_(), 0);
+    }
+
+    lcd.write(REG_LCDC, LCD_ENABLED_FLAG | BG_ENABLED_FLAG)
+        .unwrap();
+    let enabled_at = cycle + 4;
+    loop {
+        cycle += 4;
+        if lcd.pump_cycle(cycle).if_() & Interrupt::VBlank.bits() != 0 {
+            break;
+        }
+    }
+    assert_eq!(
+        cycle - enabled_at,
+        fb::SCREEN_SIZE.1 as u64 * LINE_CYCLE_TIME
+    );
+    assert_eq!(lcd.ly(), fb::SCREEN_SIZE.1 as u8);
+}
+
+#[test]
+fn test_bulk_vram_matches_per_byte() {
+    let mut bulk = Lcd::new(true);
+    let mut direct = Lcd::new(true);
+    let writes = |lcd: &mut Lcd| {
+        for bank in 0..2 {
+            lcd.write(REG_VBK, bank).unwrap();
+            for i in 0..0x123 {
+                lcd.write(Address(0x8FF7 + i), (i as u8).wrapping_mul(37) ^ bank)
+                    .unwrap();
+            }
+        }
+        for i in 0..8 {
+            lcd.write(RNG_LCD_OAM.0 + Address(20 + i), 0x55 + i as u8)
+                .unwrap();
+        }
+    };
+
+    writes(&mut direct);
+    bulk.begin_bulk_vram();
+    writes(&mut bulk);
+    assert_ne!(bulk.tiles[..], direct.tiles[..]);
+    bulk.end_bulk_vram();
+
+    assert_eq!(bulk.tiles[..], direct.tiles[..]);
+    assert_eq!(bulk.objs, direct.objs);
+}
+
+#[test]
+fn test_dirty_tiles() {
+    let mut lcd = Lcd::new(true);
+    lcd.dirty_tiles_since_last_frame();
+
+    lcd.write(Address(0x8050), 0xFF).unwrap();
+    lcd.write(Address(0x8003), 0x12).unwrap();
+    lcd.write(Address(0x8002), 0x34).unwrap();
+    assert_eq!(lcd.dirty_tiles_since_last_frame(), vec![0, 5]);
+    assert!(lcd.dirty_tiles_since_last_frame().is_empty());
+
+    lcd.write(REG_VBK, 1).unwrap();
+    lcd.write(Address(0x8010), 0x56).unwrap();
+    assert_eq!(lcd.dirty_tiles_since_last_frame(), vec![TILES_PER_BANK + 1]);
+}
+
+#[test]
+fn test_dump_tilemap() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(Address(0x9800), 0x12).unwrap();
+    lcd.write(Address(0x9821), 0x80).unwrap();
+    lcd.write(Address(0x9FFF), 0x34).unwrap();
+
+    let first = lcd.dump_tilemap(true);
+    assert_eq!(first[0][0], 0x12);
+    assert_eq!(first[1][1], 0x80);
+    assert_eq!(first[0][1], 0);
+    assert_eq!(lcd.dump_tilemap(false)[31][31], 0x34);
+
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    assert_eq!(lcd.bg_tile_index(0x7F), 127);
+    assert_eq!(lcd.bg_tile_index(0x80), 128);
+    lcd.write(REG_LCDC, LCD_ENABLED_FLAG | BG_ENABLED_FLAG)
+        .unwrap();
+    assert_eq!(lcd.bg_tile_index(0x7F), 383);
+    assert_eq!(lcd.bg_tile_index(0x80), 128);
+}
+
+#[test]
+fn test_render_both_bg_maps() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0b1110_0100).unwrap();
+    for i in 0..BYTES_PER_CHAR {
+        lcd.write(Address(0x8010 + i), 0xFF).unwrap();
+    }
+    lcd.write(BG_START_2, 1).unwrap();
+
+    let (first, second) = lcd.render_both_bg_maps();
+
+    assert_eq!(first.get(0, 0), fb::DMG_COLOR_WHITE);
+    assert_eq!(second.get(0, 0), fb::DMG_COLOR_BLACK);
+    assert_eq!(first.get(8, 0), second.get(8, 0));
+}
+
+#[test]
+fn test_render_all_tiles() {
+    let mut lcd = Lcd::new(false);
+    let palette = [[0, 0, 0], [1, 1, 1], [2, 2, 2], [3, 3, 3]];
+    let tile = 17;
+    for i in 0..BYTES_PER_CHAR {
+        lcd.write(RNG_CHAR_DAT.0 + Address(tile * BYTES_PER_CHAR + i), 0xFF)
+            .unwrap();
+    }
+
+    let (output, (width, height)) = lcd.render_all_tiles(palette);
+
+    assert_eq!(output.len(), 384 * 8 * 8);
+    assert_eq!((width, height), (128, 192));
+    assert_eq!(output[8 + 8 * width], [3, 3, 3]);
+    assert_eq!(output[15 + 15 * width], [3, 3, 3]);
+    assert_eq!(output[7 + 8 * width], [0, 0, 0]);
+    assert_eq!(output[16 + 8 * width], [0, 0, 0]);
+}
+
 impl MemDevice for Lcd {
     fn read(&self, a: Address) -> Result<u8, ExecutionError> {
         if a.in_(RNG_LCD_BGDD1) {
@@ -666,12 +1352,9 @@ impl MemDevice for Lcd {
                 REG_BCPD => Ok(self.bcp[(self.bcps & PAL_DATA_IDX) as usize]),
                 REG_OCPS => Ok(self.ocps),
                 REG_OCPD => Ok(self.ocp[(self.ocps & PAL_DATA_IDX) as usize]),
-                REG_BGP => {
-                    error!("Error: BGP is a write-only register");
-                    Err(ExecutionError::BusError)
-                }
+                REG_BGP => Ok(self.bgp),
                 _ => {
-                    error!("Unimplemented LCD register {:?}", a);
+                    error!(Lcd, "Unimplemented LCD register {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
@@ -688,16 +1371,28 @@ impl MemDevice for Lcd {
         } else if a.in_(RNG_CHAR_DAT) {
             let adjusted = a + Address((self.bank_select * RNG_CHAR_DAT.len()) as u16);
             self.cdata.write(adjusted - RNG_CHAR_DAT.0, v)?;
-            self.update_tile_at(Address(adjusted.0 - adjusted.0 % 2));
+            match self.bulk_vram {
+                Some(ref mut bulk) => {
+                    let offset = (adjusted - RNG_CHAR_DAT.0).0;
+                    bulk.tiles = Some(match bulk.tiles {
+                        Some((lo, hi)) => (lo.min(offset), hi.max(offset)),
+                        None => (offset, offset),
+                    });
+                }
+                None => self.update_tile_at(Address(adjusted.0 - adjusted.0 % 2)),
+            }
             Ok(())
         } else if a.in_(RNG_LCD_OAM) {
             self.oam.write(a - RNG_LCD_OAM.0, v)?;
-            self.update_obj_at(a);
+            match self.bulk_vram {
+                Some(ref mut bulk) => bulk.objs = true,
+                None => self.update_obj_at(a),
+            }
             Ok(())
         } else {
             match a {
                 REG_LY => {
-                    error!("LY is a read only register!");
+                    error!(Lcd, "LY is a read only register!");
                     Err(ExecutionError::BusError)
                 }
                 REG_LYC => {
@@ -705,7 +1400,13 @@ impl MemDevice for Lcd {
                     Ok(())
                 }
                 REG_LCDC => {
+                    let was_enabled = self.is_lcd_enabled();
                     self.lcdc = v;
+                    if self.is_lcd_enabled() && !was_enabled {
+                        self.restart_pending = true;
+                    } else if !self.is_lcd_enabled() {
+                        self.stat = (self.stat & 0b1111_1100) | MODE_00_MASK;
+                    }
                     Ok(())
                 }
                 REG_STAT => {
@@ -780,10 +1481,93 @@ impl MemDevice for Lcd {
                     Ok(())
                 }
                 _ => {
-                    error!("Unimplemented LCD register {:?}", a);
+                    error!(Lcd, "Unimplemented LCD register {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
         }
     }
 }
+
+#[test]
+fn test_log_filter_suppresses_lcd_errors() {
+    use log::{Level, LevelFilter};
+
+    use crate::logging::{enabled, with_log_filter, Subsystem};
+
+    let lcd = Lcd::new(false);
+    with_log_filter(Subsystem::Lcd, LevelFilter::Off, || {
+        assert!(!enabled(Subsystem::Lcd, Level::Error));
+        // Filtering only silences the log, the error is still reported
+        assert!(lcd.read(Address(0xFF4C)).is_err());
+    });
+    assert!(enabled(Subsystem::Lcd, Level::Error));
+}
+
+#[test]
+fn test_framebuffer_diff() {
+    let a = fb::Framebuffer::new(fb::SCREEN_SIZE);
+    let mut b = a.clone();
+    assert_eq!(0, framebuffer_diff_count(&a, &b));
+    assert_eq!(None, first_difference(&a, &b));
+
+    b.set(17, 42, fb::DMG_COLOR_BLACK);
+    assert_eq!(1, framebuffer_diff_count(&a, &b));
+    assert_eq!(Some((17, 42)), first_difference(&a, &b));
+
+    let c = fb::Framebuffer::new((8, 4));
+    assert_eq!(a.raw().len(), framebuffer_diff_count(&a, &c));
+    assert_eq!(a.raw().len(), framebuffer_diff_count(&c, &a));
+    assert_eq!(Some((0, 0)), first_difference(&a, &c));
+}
+
+#[test]
+fn test_bg_color0_transparent() {
+    let mut lcd = Lcd::new(false);
+    lcd.write(
+        REG_LCDC,
+        LCD_ENABLED_FLAG | BGD_CHAR_DAT_FLAG | BG_ENABLED_FLAG,
+    )
+    .unwrap();
+    lcd.write(REG_BGP, 0xE4).unwrap();
+    // Tile 1 is solid color 3 and sits over the first 8 pixels
+    lcd.write(Address(0x9800), 1).unwrap();
+    for a in 0x8010..0x8020 {
+        lcd.write(Address(a), 0xFF).unwrap();
+    }
+
+    lcd.render_screen_row();
+    let rgba = lcd.get_back_framebuffer().to_rgba();
+    assert_eq!(rgba[3], 0xFF);
+    assert_eq!(rgba[8 * 4 + 3], 0xFF);
+
+    lcd.set_bg_color0_transparent(true);
+    lcd.render_screen_row();
+    let rgba = lcd.get_back_framebuffer().to_rgba();
+    assert_eq!(rgba[3], 0xFF);
+    assert_eq!(&rgba[8 * 4..8 * 4 + 4], &[234, 255, 186, 0]);
+}
+
+#[test]
+fn test_all_palettes() {
+    let mut lcd = Lcd::new(true);
+    lcd.write(REG_BCPS, 0x80 | (2 * 8 + 2)).unwrap(); // Palette 2, color 1
+    lcd.write(REG_BCPD, 0x1F).unwrap();
+    lcd.write(REG_BCPD, 0x00).unwrap();
+    lcd.write(REG_OCPS, 0x80 | (7 * 8 + 3 * 2)).unwrap(); // Palette 7, color 3
+    lcd.write(REG_OCPD, 0x00).unwrap();
+    lcd.write(REG_OCPD, 0x7C).unwrap();
+
+    let (bg, obj) = lcd.all_palettes();
+    assert_eq!((bg.len(), obj.len()), (8, 8));
+    assert_eq!(bg[2][1], [255, 0, 0]);
+    assert_eq!(obj[7][3], [0, 0, 255]);
+
+    let mut lcd = Lcd::new(false);
+    lcd.write(REG_BGP, 0xE4).unwrap();
+    lcd.write(REG_OBP1, 0x1B).unwrap();
+    let (bg, obj) = lcd.all_palettes();
+    assert_eq!(bg, vec![fb::DMG_COLORS]);
+    assert_eq!(obj.len(), 2);
+    assert_eq!(obj[1], [3, 2, 1, 0].map(|i| fb::DMG_COLORS[i]));
+}
@@ -0,0 +1,50 @@
+use super::fb::Framebuffer;
+
+pub struct FrameCapture {
+    frames: Vec<Vec<u8>>,
+    frame_skip: usize,
+    max_frames: usize,
+    skipped: usize,
+}
+
+impl FrameCapture {
+    pub fn new(frame_skip: usize, max_frames: usize) -> FrameCapture {
+        FrameCapture {
+            frames: Vec::new(),
+            frame_skip,
+            max_frames,
+            skipped: 0,
+        }
+    }
+
+    pub fn record(&mut self, fb: &Framebuffer) {
+        if self.frames.len() >= self.max_frames {
+            return;
+        }
+
+        if self.skipped < self.frame_skip {
+            self.skipped += 1;
+            return;
+        }
+
+        self.skipped = 0;
+        self.frames.push(fb.to_rgba());
+    }
+
+    pub fn into_frames(self) -> Vec<Vec<u8>> {
+        self.frames
+    }
+}
+
+#[test]
+fn test_capture_skip_and_cap() {
+    let fb = Framebuffer::new((2, 2));
+    let mut capture = FrameCapture::new(1, 2);
+    for _ in 0..10 {
+        capture.record(&fb);
+    }
+
+    let frames = capture.into_frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].len(), 2 * 2 * 4);
+}
@@ -0,0 +1,45 @@
+use super::fb::Pixel;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CvdType {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl CvdType {
+    fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            CvdType::Protanopia => [
+                [0.567, 0.433, 0.0],
+                [0.558, 0.442, 0.0],
+                [0.0, 0.242, 0.758],
+            ],
+            CvdType::Deuteranopia => [[0.625, 0.375, 0.0], [0.7, 0.3, 0.0], [0.0, 0.3, 0.7]],
+            CvdType::Tritanopia => [[0.95, 0.05, 0.0], [0.0, 0.433, 0.567], [0.0, 0.475, 0.525]],
+        }
+    }
+
+    pub fn apply(self, pixel: Pixel) -> Pixel {
+        let m = self.matrix();
+        let mut out = [0; 3];
+        for (o, row) in out.iter_mut().zip(m.iter()) {
+            let v: f32 = row
+                .iter()
+                .zip(pixel.iter())
+                .map(|(k, c)| k * f32::from(*c))
+                .sum();
+            *o = v.round().clamp(0., 255.) as u8;
+        }
+        out
+    }
+}
+
+#[test]
+fn test_deuteranopia_green() {
+    assert_eq!(CvdType::Deuteranopia.apply([0, 255, 0]), [96, 77, 77]);
+    assert_eq!(
+        CvdType::Deuteranopia.apply([255, 255, 255]),
+        [255, 255, 255]
+    );
+}
@@ -17,6 +17,8 @@ pub type Pixel = [u8; 3];
 #[derive(Clone)]
 pub struct Framebuffer {
     data: Vec<Pixel>,
+    // Pixels written out with an alpha of 0 by `to_rgba`
+    transparent: Vec<bool>,
     size: (usize, usize),
 }
 
@@ -26,22 +28,41 @@ impl Framebuffer {
         v.resize(width * height, DMG_COLOR_WHITE);
         Framebuffer {
             data: v,
+            transparent: vec![false; width * height],
             size: (width, height),
         }
     }
 
     pub fn set(&mut self, x: usize, y: usize, color: Pixel) {
         self.data[x + y * self.size.0] = color;
+        self.transparent[x + y * self.size.0] = false;
+    }
+
+    pub fn set_transparent(&mut self, x: usize, y: usize) {
+        self.transparent[x + y * self.size.0] = true;
     }
 
     pub fn get(&self, x: usize, y: usize) -> Pixel {
         self.data[x + y * self.size.0]
     }
 
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
     pub fn raw(&self) -> &[Pixel] {
         &self.data
     }
 
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.data.len() * 4);
+        for (p, &transparent) in self.data.iter().zip(&self.transparent) {
+            let alpha = if transparent { 0 } else { 0xFF };
+            rgba.extend_from_slice(&[p[0], p[1], p[2], alpha]);
+        }
+        rgba
+    }
+
     pub fn draw_wrapping_vline(&mut self, x: usize, y: usize, len: usize, color: Pixel) {
         for i in 0..len {
             let y = (y + i) % self.size.1;
@@ -91,6 +112,10 @@ impl TentativePixel {
     pub fn color(self) -> Pixel {
         self.color
     }
+
+    pub fn data_was_zero(self) -> bool {
+        self.data_was_zero
+    }
 }
 
 pub fn resolve_pixel(mode: SystemMode, oam: Option<TentativePixel>, bg: TentativePixel) -> Pixel {
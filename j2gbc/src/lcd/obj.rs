@@ -52,3 +52,24 @@ impl Obj {
         (self.flags & OBJ_BANK_FLAG) >> 3
     }
 }
+
+#[test]
+fn test_cgb_flag_bits() {
+    let obj = Obj::new(0, 0, 0, 0b0000_1101, SystemMode::CGB);
+    assert_eq!(obj.cgb_palette(), 5);
+    assert_eq!(obj.bank(), 1);
+    assert!(!obj.high_palette());
+    assert!(!obj.xflip() && !obj.yflip() && !obj.priority());
+
+    let obj = Obj::new(0, 0, 0, 0xFF, SystemMode::CGB);
+    assert_eq!(obj.cgb_palette(), 7);
+    assert_eq!(obj.bank(), 1);
+    assert!(!obj.high_palette());
+    assert!(obj.xflip() && obj.yflip() && obj.priority());
+
+    // DMG ignores the CGB bits but keeps its own palette select
+    let obj = Obj::new(0, 0, 0, 0xFF, SystemMode::DMG);
+    assert_eq!(obj.cgb_palette(), 0);
+    assert_eq!(obj.bank(), 0);
+    assert!(obj.high_palette());
+}
@@ -1,44 +1,82 @@
-use j2ds::{Timer, TimerEvent};
+use j2ds::{next_timer_event, Timer, TimerEvent};
 
-use super::{LINE_CYCLE_TIME, LYC_MATCH_FLAG, LYC_MATCH_INT_FLAG, TOTAL_SCANLINES};
+use super::{
+    LINE_CYCLE_TIME, LYC_MATCH_FLAG, LYC_MATCH_INT_FLAG, SCREEN_CYCLE_TIME, TOTAL_SCANLINES,
+};
 use crate::cpu::Interrupt;
 
+// How long LY reads 153 before it reads 0 for the rest of the last line
+const LAST_LINE_LY_DURATION: u64 = 4;
+
 pub struct ScanlineSweeper {
     ly: u8,
     lyc: u8,
     interrupt_enabled: bool,
+    // Set once LY reads 0 while the last line is still being swept
+    early_zero: bool,
     timer: Timer,
+    early_zero_timer: Timer,
+    // Both timers count from the cycle the sweep was last restarted at
+    timer_offset: u64,
 }
 
 impl ScanlineSweeper {
     pub fn new() -> ScanlineSweeper {
-        let mut timer = Timer::new(LINE_CYCLE_TIME, 0, 0);
-        timer.update(0);
-        ScanlineSweeper {
+        let mut sweeper = ScanlineSweeper {
             ly: 0,
             lyc: 0,
             interrupt_enabled: false,
-            timer,
-        }
+            early_zero: false,
+            timer: Timer::new(LINE_CYCLE_TIME, 0, 0),
+            early_zero_timer: early_zero_timer(),
+            timer_offset: 0,
+        };
+        sweeper.restart(0);
+        sweeper
+    }
+
+    pub fn restart(&mut self, cycle: u64) {
+        self.timer = Timer::new(LINE_CYCLE_TIME, 0, 0);
+        self.timer.update(0);
+        self.early_zero_timer = early_zero_timer();
+        self.timer_offset = cycle;
+        self.ly = 0;
+        self.early_zero = false;
     }
 
     pub fn pump_cycle(&mut self, cycle: u64) -> Option<Interrupt> {
+        let mut inter = None;
+        let cycle = cycle - self.timer_offset;
+
+        if self.early_zero_timer.update(cycle) == Some(TimerEvent::RisingEdge) {
+            self.early_zero = true;
+            if self.lyc == 0 && self.interrupt_enabled {
+                inter = Some(Interrupt::LCDC);
+            }
+        }
+
         if self.timer.update(cycle) == Some(TimerEvent::RisingEdge) {
             assert_eq!(self.timer.update(cycle), None); // We should never end up too far behind
             self.ly = (self.ly + 1) % TOTAL_SCANLINES as u8;
 
-            if self.ly == self.lyc && self.interrupt_enabled {
-                Some(Interrupt::LCDC)
-            } else {
-                None
+            // A match on LY 0 was already raised during the last line
+            let already_matched = self.early_zero;
+            self.early_zero = false;
+            if self.ly == self.lyc && self.interrupt_enabled && !already_matched {
+                inter = Some(Interrupt::LCDC);
             }
-        } else {
-            None
         }
+
+        inter
     }
 
+    // The line as read through the LY register
     pub fn ly(&self) -> u8 {
-        self.ly
+        if self.early_zero {
+            0
+        } else {
+            self.ly
+        }
     }
 
     pub fn lyc(&self) -> u8 {
@@ -50,7 +88,7 @@ impl ScanlineSweeper {
     }
 
     pub fn stat_flags(&self) -> u8 {
-        if self.ly == self.lyc {
+        if self.ly() == self.lyc {
             LYC_MATCH_FLAG
         } else {
             0
@@ -61,8 +99,8 @@ impl ScanlineSweeper {
         self.interrupt_enabled = (flags & LYC_MATCH_INT_FLAG) != 0;
     }
 
-    pub fn timer(&self) -> Timer {
-        self.timer
+    pub fn get_next_event_cycle(&self) -> u64 {
+        self.timer_offset + next_timer_event(&[self.timer, self.early_zero_timer])
     }
 
     pub fn on_visible_scanline(&self) -> bool {
@@ -70,6 +108,14 @@ impl ScanlineSweeper {
     }
 }
 
+fn early_zero_timer() -> Timer {
+    Timer::new(
+        SCREEN_CYCLE_TIME,
+        (TOTAL_SCANLINES - 1) * LINE_CYCLE_TIME + LAST_LINE_LY_DURATION,
+        0,
+    )
+}
+
 #[test]
 fn test_sweep_and_wrap() {
     let mut sweeper = ScanlineSweeper::new();
@@ -103,3 +149,74 @@ fn test_lyc() {
     );
     assert_eq!(sweeper.stat_flags(), LYC_MATCH_FLAG);
 }
+
+#[test]
+fn test_ly_reads_zero_early_on_last_line() {
+    let mut sweeper = ScanlineSweeper::new();
+    let last_line = (TOTAL_SCANLINES - 1) * LINE_CYCLE_TIME;
+
+    let mut seen = vec![];
+    for cycle in (0..=last_line + LINE_CYCLE_TIME).step_by(4) {
+        sweeper.pump_cycle(cycle);
+        if seen.last() != Some(&sweeper.ly()) {
+            seen.push(sweeper.ly());
+        }
+    }
+    let mut expected: Vec<u8> = (0..=153).collect();
+    expected.push(0);
+    assert_eq!(seen, expected);
+
+    let mut sweeper = ScanlineSweeper::new();
+    for scanline in 1..TOTAL_SCANLINES {
+        sweeper.pump_cycle(LINE_CYCLE_TIME * scanline);
+    }
+    assert_eq!(sweeper.ly(), 153);
+    sweeper.pump_cycle(last_line + LAST_LINE_LY_DURATION - 1);
+    assert_eq!(sweeper.ly(), 153);
+    sweeper.pump_cycle(last_line + LAST_LINE_LY_DURATION);
+    assert_eq!(sweeper.ly(), 0);
+    assert!(!sweeper.on_visible_scanline());
+    sweeper.pump_cycle(last_line + LINE_CYCLE_TIME);
+    assert_eq!(sweeper.ly(), 0);
+    assert!(sweeper.on_visible_scanline());
+}
+
+#[test]
+fn test_lyc_zero_matches_once_on_last_line() {
+    let mut sweeper = ScanlineSweeper::new();
+    sweeper.set_lyc(0);
+    sweeper.update_stat(LYC_MATCH_INT_FLAG);
+    let last_line = (TOTAL_SCANLINES - 1) * LINE_CYCLE_TIME;
+
+    for scanline in 1..TOTAL_SCANLINES {
+        assert_eq!(sweeper.pump_cycle(LINE_CYCLE_TIME * scanline), None);
+    }
+    assert_eq!(
+        sweeper.pump_cycle(last_line + LAST_LINE_LY_DURATION),
+        Some(Interrupt::LCDC)
+    );
+    assert_eq!(sweeper.stat_flags(), LYC_MATCH_FLAG);
+    assert_eq!(sweeper.pump_cycle(last_line + LINE_CYCLE_TIME), None);
+    assert_eq!(sweeper.stat_flags(), LYC_MATCH_FLAG);
+}
+
+#[test]
+fn test_restart_mid_frame() {
+    let mut sweeper = ScanlineSweeper::new();
+    for scanline in 1..20 {
+        sweeper.pump_cycle(LINE_CYCLE_TIME * scanline);
+    }
+    assert_eq!(sweeper.ly(), 19);
+
+    let start = 3 * SCREEN_CYCLE_TIME + 100;
+    sweeper.restart(start);
+    assert_eq!(sweeper.ly(), 0);
+    assert_eq!(sweeper.get_next_event_cycle(), start + LINE_CYCLE_TIME);
+
+    for scanline in 1..TOTAL_SCANLINES {
+        sweeper.pump_cycle(start + LINE_CYCLE_TIME * scanline);
+        assert_eq!(sweeper.ly(), scanline as u8);
+    }
+    sweeper.pump_cycle(start + (TOTAL_SCANLINES - 1) * LINE_CYCLE_TIME + LAST_LINE_LY_DURATION);
+    assert_eq!(sweeper.ly(), 0);
+}
@@ -0,0 +1,77 @@
+use super::fb::{Framebuffer, Pixel};
+
+// Clockwise rotation of the output, applied after any flips
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputTransform {
+    pub rotation: Rotation,
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+impl OutputTransform {
+    pub fn size(self, (width, height): (usize, usize)) -> (usize, usize) {
+        match self.rotation {
+            Rotation::None | Rotation::Cw180 => (width, height),
+            Rotation::Cw90 | Rotation::Cw270 => (height, width),
+        }
+    }
+
+    pub fn apply(self, fb: &Framebuffer) -> Vec<Pixel> {
+        let (width, height) = fb.size();
+        let (out_width, out_height) = self.size((width, height));
+
+        let mut out = Vec::with_capacity(out_width * out_height);
+        for oy in 0..out_height {
+            for ox in 0..out_width {
+                let (mut x, mut y) = match self.rotation {
+                    Rotation::None => (ox, oy),
+                    Rotation::Cw90 => (oy, height - 1 - ox),
+                    Rotation::Cw180 => (width - 1 - ox, height - 1 - oy),
+                    Rotation::Cw270 => (width - 1 - oy, ox),
+                };
+                if self.flip_h {
+                    x = width - 1 - x;
+                }
+                if self.flip_v {
+                    y = height - 1 - y;
+                }
+                out.push(fb.get(x, y));
+            }
+        }
+        out
+    }
+}
+
+#[test]
+fn test_rotate_90() {
+    let mut fb = Framebuffer::new((3, 2));
+    fb.set(0, 0, [1, 0, 0]);
+    fb.set(2, 1, [2, 0, 0]);
+
+    let transform = OutputTransform {
+        rotation: Rotation::Cw90,
+        ..OutputTransform::default()
+    };
+    assert_eq!(transform.size(fb.size()), (2, 3));
+
+    let out = transform.apply(&fb);
+    // The top-left corner ends up top-right, bottom-right ends up bottom-left
+    assert_eq!(out[1], [1, 0, 0]);
+    assert_eq!(out[2 * 2], [2, 0, 0]);
+
+    let transform = OutputTransform {
+        flip_h: true,
+        ..transform
+    };
+    let out = transform.apply(&fb);
+    assert_eq!(out[2 * 2 + 1], [1, 0, 0]);
+}
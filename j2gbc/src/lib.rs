@@ -1,6 +1,9 @@
 #![allow(unknown_lints)]
 #![allow(clippy::upper_case_acronyms)]
 
+#[macro_use]
+mod logging;
+
 mod alu;
 mod audio;
 mod cart;
@@ -14,12 +17,20 @@ mod mbc;
 mod mem;
 mod mmu;
 mod mmu_exceptions;
+mod rng;
 mod system;
 mod timer;
 
 pub use crate::{
-    audio::{AudioSink, NullSink},
+    audio::{AudioBuffer, AudioSink, NullSink, TurboAudio, UnderrunPolicy},
+    cart::{CartHeader, LoadOptions, LoadWarning},
     input::Button,
-    lcd::fb::{Framebuffer, SCREEN_SIZE},
-    system::System,
+    lcd::{
+        fb::{Framebuffer, SCREEN_SIZE},
+        first_difference, framebuffer_diff_count, CvdType, Rotation,
+    },
+    logging::{set_log_filter, Subsystem},
+    mem::RamFill,
+    mmu::OpenBus,
+    system::{replay, FrameOutput, Governor, GovernorAction, System},
 };
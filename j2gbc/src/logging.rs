@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter};
+
+// Thin wrappers over the `log` macros that first check the per-subsystem
+// filter, e.g. `error!(Lcd, "Unimplemented LCD register {:?}", a)`.
+macro_rules! gated_log {
+    ($sub:ident, $level:ident, $($arg:tt)+) => {
+        if $crate::logging::enabled(
+            $crate::logging::Subsystem::$sub,
+            log::Level::$level,
+        ) {
+            log::log!(log::Level::$level, $($arg)+);
+        }
+    };
+}
+
+macro_rules! error {
+    ($sub:ident, $($arg:tt)+) => { gated_log!($sub, Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($sub:ident, $($arg:tt)+) => { gated_log!($sub, Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($sub:ident, $($arg:tt)+) => { gated_log!($sub, Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($sub:ident, $($arg:tt)+) => { gated_log!($sub, Debug, $($arg)+) };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    Cpu,
+    Lcd,
+    Audio,
+    Mmu,
+    Cart,
+    System,
+}
+
+// Everything is let through by default; the global `log` max level still
+// applies on top of this.
+static FILTERS: [AtomicUsize; 6] = [
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+    AtomicUsize::new(LevelFilter::Trace as usize),
+];
+
+pub fn set_log_filter(subsystem: Subsystem, filter: LevelFilter) {
+    FILTERS[subsystem as usize].store(filter as usize, Ordering::Relaxed);
+}
+
+pub fn log_filter(subsystem: Subsystem) -> LevelFilter {
+    match FILTERS[subsystem as usize].load(Ordering::Relaxed) {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+pub fn enabled(subsystem: Subsystem, level: Level) -> bool {
+    level <= log_filter(subsystem)
+}
+
+// Tests run in parallel, so changes to the global filters are serialized and
+// undone once the closure returns or panics
+#[cfg(test)]
+pub fn with_log_filter<T>(subsystem: Subsystem, filter: LevelFilter, f: impl FnOnce() -> T) -> T {
+    use std::sync::Mutex;
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    struct Restore(Subsystem, LevelFilter);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            set_log_filter(self.0, self.1);
+        }
+    }
+
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let _restore = Restore(subsystem, log_filter(subsystem));
+    set_log_filter(subsystem, filter);
+    f()
+}
+
+#[test]
+fn test_log_filter() {
+    with_log_filter(Subsystem::Cart, LevelFilter::Warn, || {
+        assert!(enabled(Subsystem::Cart, Level::Error));
+        assert!(enabled(Subsystem::Cart, Level::Warn));
+        assert!(!enabled(Subsystem::Cart, Level::Info));
+        assert!(enabled(Subsystem::Cpu, Level::Trace));
+    });
+    assert!(enabled(Subsystem::Cart, Level::Trace));
+}
@@ -1,12 +1,101 @@
 pub mod mbc0;
 pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
 pub mod mbc5;
 
+use self::mbc0::Mbc0;
+use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
+use self::mbc5::Mbc5;
+use super::cart::{header_ram_size, OFF_CART_TYPE, OFF_RAM_SIZE};
 use super::mem::{Address, ExtendedAddress, MemDevice};
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MbcKind {
+    Mbc0,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
 pub trait Mbc: MemDevice {
+    fn kind(&self) -> MbcKind;
+
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress;
 
+    // Only MBC1 can bank the 0x0000-0x3FFF window
+    fn map_address_into_rom_bank0(&self, a: Address) -> ExtendedAddress {
+        ExtendedAddress(u32::from(a.0))
+    }
+
     fn get_sram(&self) -> &[u8];
     fn set_sram(&mut self, buf: &[u8]);
+
+    fn has_battery(&self) -> bool {
+        false
+    }
+}
+
+// The one table of cart type bytes, as found at 0x0147 of the header, that have an MBC
+pub fn kind_for_cart_type(type_: u8) -> Option<MbcKind> {
+    match type_ {
+        0x00 => Some(MbcKind::Mbc0),
+        0x01..=0x03 => Some(MbcKind::Mbc1),
+        0x05 | 0x06 => Some(MbcKind::Mbc2),
+        0x0F..=0x13 => Some(MbcKind::Mbc3),
+        0x19..=0x1E => Some(MbcKind::Mbc5),
+        _ => None,
+    }
+}
+
+pub fn is_supported(type_: u8) -> bool {
+    kind_for_cart_type(type_).is_some()
+}
+
+pub fn for_cart_type(type_: u8, rom: Vec<u8>) -> Option<Box<dyn Mbc + Send>> {
+    let mbc: Box<dyn Mbc + Send> = match kind_for_cart_type(type_)? {
+        MbcKind::Mbc0 => Box::new(Mbc0::new(rom)),
+        MbcKind::Mbc1 => Box::new(Mbc1::new(rom)),
+        MbcKind::Mbc2 => Box::new(Mbc2::new(rom)),
+        MbcKind::Mbc3 => Box::new(Mbc3::new(rom)),
+        MbcKind::Mbc5 => Box::new(Mbc5::new(rom)),
+    };
+    Some(mbc)
+}
+
+// None for a missing or unknown RAM size code
+fn header_ram_len(rom: &[u8]) -> Option<usize> {
+    rom.get(OFF_RAM_SIZE)
+        .and_then(|code| header_ram_size(*code))
+}
+
+fn header_has_battery(rom: &[u8]) -> bool {
+    matches!(
+        rom[OFF_CART_TYPE],
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+    )
+}
+
+#[test]
+fn test_for_cart_type() {
+    let kind = |type_| {
+        let mut rom = vec![0; 0x8000];
+        rom[OFF_CART_TYPE] = type_;
+        for_cart_type(type_, rom).map(|mbc| mbc.kind())
+    };
+
+    assert_eq!(kind(0x00), Some(MbcKind::Mbc0));
+    assert_eq!(kind(0x01), Some(MbcKind::Mbc1));
+    assert_eq!(kind(0x03), Some(MbcKind::Mbc1));
+    assert_eq!(kind(0x06), Some(MbcKind::Mbc2));
+    assert_eq!(kind(0x0F), Some(MbcKind::Mbc3));
+    assert_eq!(kind(0x13), Some(MbcKind::Mbc3));
+    assert_eq!(kind(0x19), Some(MbcKind::Mbc5));
+    assert_eq!(kind(0x1E), Some(MbcKind::Mbc5));
+    assert_eq!(kind(0x22), None);
+    assert_eq!(kind(0xFC), None);
+    assert!(!is_supported(0x04));
 }
@@ -1,10 +1,9 @@
-use log::error;
-
-use super::Mbc;
+use super::{header_has_battery, Mbc, MbcKind};
 use crate::error::ExecutionError;
 use crate::mem::{Address, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1};
 
 pub struct Mbc0 {
+    battery: bool,
     rom: Vec<u8>,
     ram: Ram,
 }
@@ -12,6 +11,7 @@ pub struct Mbc0 {
 impl Mbc0 {
     pub fn new(rom: Vec<u8>) -> Mbc0 {
         Mbc0 {
+            battery: header_has_battery(&rom),
             rom,
             ram: Ram::new(RNG_EXT_RAM.len()),
         }
@@ -25,7 +25,7 @@ impl MemDevice for Mbc0 {
         } else if a.in_(RNG_EXT_RAM) {
             self.ram.read(a - RNG_EXT_RAM.0)
         } else {
-            error!("Address out of range for MBC 0");
+            error!(Cart, "Address out of range for MBC 0");
             Err(ExecutionError::BusError)
         }
     }
@@ -34,13 +34,17 @@ impl MemDevice for Mbc0 {
         if a.in_(RNG_EXT_RAM) {
             self.ram.write(a - RNG_EXT_RAM.0, v)
         } else {
-            error!("Unknown MBC0 register {}", a);
+            error!(Cart, "Unknown MBC0 register {}", a);
             Err(ExecutionError::BusError)
         }
     }
 }
 
 impl Mbc for Mbc0 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc0
+    }
+
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
         ExtendedAddress(u32::from(a.0))
     }
@@ -52,4 +56,8 @@ impl Mbc for Mbc0 {
     fn set_sram(&mut self, buf: &[u8]) {
         self.ram.data[..buf.len()].clone_from_slice(buf);
     }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
 }
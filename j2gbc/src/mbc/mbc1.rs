@@ -1,6 +1,4 @@
-use log::error;
-
-use super::Mbc;
+use super::{header_has_battery, header_ram_len, Mbc, MbcKind};
 use crate::error::ExecutionError;
 use crate::mem::{
     Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
@@ -14,6 +12,7 @@ const MAKS_UPPER_BANK_SELCET: u8 = 0b0000_0011;
 const MASK_LOWER_BANK_SELECT: u8 = 0b0001_1111;
 
 pub struct Mbc1 {
+    battery: bool,
     ram_protected: bool,
     rom: Vec<u8>,
     lower_bank_select: usize,
@@ -24,13 +23,18 @@ pub struct Mbc1 {
 
 impl Mbc1 {
     pub fn new(rom: Vec<u8>) -> Mbc1 {
+        // Never less than one bank, so saves from before the header was consulted still fit
+        let ram_len = header_ram_len(&rom)
+            .unwrap_or(0)
+            .clamp(RNG_EXT_RAM.len(), RNG_EXT_RAM.len() * 4);
         Mbc1 {
+            battery: header_has_battery(&rom),
             ram_protected: true,
             rom,
             upper_bank_controls_rom: true,
             upper_bank_select: 0,
             lower_bank_select: 1,
-            ram: Ram::new(RNG_EXT_RAM.len() * 4),
+            ram: Ram::new(ram_len),
         }
     }
 
@@ -40,7 +44,8 @@ impl Mbc1 {
         } else {
             0
         };
-        Address(((a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank) as u16)
+        let index = (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank;
+        Address((index % self.ram.data.len()) as u16)
     }
 }
 
@@ -50,7 +55,7 @@ impl MemDevice for Mbc1 {
             let index = self.map_address_into_rom(a).0 as usize;
             Ok(self.rom[index])
         } else if a.in_(RNG_EXT_RAM) {
-            self.ram.read(a - RNG_EXT_RAM.0)
+            self.ram.read(self.map_address_into_ram(a))
         } else {
             unreachable!();
         }
@@ -65,7 +70,7 @@ impl MemDevice for Mbc1 {
             Ok(())
         } else if a.in_(RNG_EXT_RAM) {
             if self.ram_protected {
-                error!("Error: RAM is not writable right now");
+                error!(Cart, "Error: RAM is not writable right now");
                 Err(ExecutionError::ProtectionFault)
             } else {
                 let mapped = self.map_address_into_ram(a);
@@ -81,21 +86,34 @@ impl MemDevice for Mbc1 {
             self.upper_bank_controls_rom = v == 0;
             Ok(())
         } else {
-            error!("Unimplemented MBC1 register");
+            error!(Cart, "Unimplemented MBC1 register");
             Err(ExecutionError::BusError)
         }
     }
 }
 
 impl Mbc for Mbc1 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc1
+    }
+
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
-        if self.upper_bank_controls_rom && self.upper_bank_select != 0 {
-            unimplemented!();
-        }
+        // The zero check only sees the lower 5 bits, so banks 0x20, 0x40 and
+        // 0x60 alias to 0x21, 0x41 and 0x61. The mode doesn't affect this window.
+        let bank = self.upper_bank_select << 5 | self.lower_bank_select;
+        let index = RNG_ROM_BANK1.len() * (bank - 1) + a.0 as usize;
+        ExtendedAddress((index % self.rom.len()) as u32)
+    }
 
-        ExtendedAddress(
-            (RNG_ROM_BANK1.len() * (self.lower_bank_select - 1)) as u32 + u32::from(a.0),
-        )
+    // In mode 1 the upper bits select which bank appears at 0x0000 too
+    fn map_address_into_rom_bank0(&self, a: Address) -> ExtendedAddress {
+        let bank = if self.upper_bank_controls_rom {
+            0
+        } else {
+            self.upper_bank_select << 5
+        };
+        let index = RNG_ROM_BANK1.len() * bank + a.0 as usize;
+        ExtendedAddress((index % self.rom.len()) as u32)
     }
 
     fn get_sram(&self) -> &[u8] {
@@ -103,6 +121,101 @@ impl Mbc for Mbc1 {
     }
 
     fn set_sram(&mut self, buf: &[u8]) {
-        self.ram.data[..buf.len()].clone_from_slice(buf);
+        let len = buf.len().min(self.ram.data.len());
+        self.ram.data[..len].clone_from_slice(&buf[..len]);
     }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+#[test]
+fn test_rom_bank_switching() {
+    let mut mbc = Mbc1::new(vec![0; 0x10_0000]);
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x4000);
+
+    mbc.write(Address(0x2000), 0x05).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x1_4000);
+    assert_eq!(mbc.map_address_into_rom(Address(0x7FFF)).0, 0x1_7FFF);
+
+    mbc.write(Address(0x3FFF), 0x00).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x4000);
+
+    mbc.write(Address(0x2000), 0xE3).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x3 * 0x4000);
+}
+
+#[test]
+fn test_upper_rom_bank_bits() {
+    let mut mbc = Mbc1::new(vec![0; 0x10_0000]);
+
+    mbc.write(Address(0x4000), 0x01).unwrap();
+    mbc.write(Address(0x2000), 0x02).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x22 * 0x4000);
+
+    mbc.write(Address(0x2000), 0x00).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x21 * 0x4000);
+
+    assert_eq!(mbc.map_address_into_rom_bank0(Address(0x0000)).0, 0);
+
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x21 * 0x4000);
+    assert_eq!(
+        mbc.map_address_into_rom_bank0(Address(0x0000)).0,
+        0x20 * 0x4000
+    );
+    assert_eq!(
+        mbc.map_address_into_rom_bank0(Address(0x3FFF)).0,
+        0x20 * 0x4000 + 0x3FFF
+    );
+
+    // Smaller carts don't have the pins, so bank 0 stays put
+    let mut mbc = Mbc1::new(vec![0; 0x8_0000]);
+    mbc.write(Address(0x4000), 0x01).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(mbc.map_address_into_rom_bank0(Address(0x0100)).0, 0x0100);
+}
+
+#[test]
+fn test_rom_bank_wraps_to_rom_size() {
+    let mut mbc = Mbc1::new(vec![0; 0x2_0000]);
+    mbc.write(Address(0x2000), 0x09).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x4000);
+}
+
+#[test]
+fn test_ram_size_from_header() {
+    use crate::cart::OFF_RAM_SIZE;
+
+    let ram_len = |code| {
+        let mut rom = vec![0; 0x8000];
+        rom[OFF_RAM_SIZE] = code;
+        Mbc1::new(rom).get_sram().len()
+    };
+
+    assert_eq!(ram_len(0x00), 0x2000);
+    assert_eq!(ram_len(0x02), 0x2000);
+    assert_eq!(ram_len(0x03), 0x8000);
+    assert_eq!(ram_len(0xFF), 0x2000);
+}
+
+#[test]
+fn test_ram_banking() {
+    let mut rom = vec![0; 0x8000];
+    rom[crate::cart::OFF_RAM_SIZE] = 0x03;
+    let mut mbc = Mbc1::new(rom);
+    assert!(mbc.write(Address(0xA000), 0x12).is_err());
+
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    mbc.write(Address(0x4000), 0x02).unwrap();
+    mbc.write(Address(0xA000), 0x42).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)).unwrap(), 0x42);
+
+    mbc.write(Address(0x4000), 0x00).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)).unwrap(), 0x00);
+
+    mbc.write(Address(0x4000), 0x02).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)).unwrap(), 0x42);
 }
@@ -0,0 +1,131 @@
+use super::{header_has_battery, Mbc, MbcKind};
+use crate::error::ExecutionError;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_REGISTERS: AddressRange = AddressRange(Address(0x0000), Address(0x4000));
+// Bit 8 of the address picks between the RAM enable and ROM bank registers
+const REGISTER_SELECT_BIT: u16 = 0b1_0000_0000;
+const MASK_ROM_BANK_SELECT: u8 = 0b0000_1111;
+const RAM_SIZE: usize = 512;
+// Only the low nibble is stored, the upper one reads back as open bus
+const MASK_RAM_UNUSED: u8 = 0b1111_0000;
+
+pub struct Mbc2 {
+    battery: bool,
+    ram_protected: bool,
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram: Ram,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Mbc2 {
+        Mbc2 {
+            battery: header_has_battery(&rom),
+            ram_protected: true,
+            rom,
+            rom_bank_select: 1,
+            ram: Ram::new(RAM_SIZE),
+        }
+    }
+
+    // The 512 nibbles are echoed through the whole external RAM range
+    fn map_address_into_ram(&self, a: Address) -> Address {
+        Address((a - RNG_EXT_RAM.0).0 % RAM_SIZE as u16)
+    }
+}
+
+impl MemDevice for Mbc2 {
+    fn read(&self, a: Address) -> Result<u8, ExecutionError> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            Ok(self.ram.read(self.map_address_into_ram(a))? | MASK_RAM_UNUSED)
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                error!(Cart, "Error: RAM is not writable right now");
+                Err(ExecutionError::ProtectionFault)
+            } else {
+                let mapped = self.map_address_into_ram(a);
+                self.ram.write(mapped, v & !MASK_RAM_UNUSED)
+            }
+        } else if a.in_(RNG_REGISTERS) {
+            if a.0 & REGISTER_SELECT_BIT == 0 {
+                self.ram_protected = v & 0x0F != 0x0A;
+            } else {
+                self.rom_bank_select = (v & MASK_ROM_BANK_SELECT) as usize;
+                if self.rom_bank_select == 0 {
+                    self.rom_bank_select = 1;
+                }
+            }
+            Ok(())
+        } else {
+            error!(Cart, "Unimplemented MBC2 register {}", a);
+            Err(ExecutionError::BusError)
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc2
+    }
+
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let index = RNG_ROM_BANK1.len() * (self.rom_bank_select - 1) + a.0 as usize;
+        ExtendedAddress((index % self.rom.len()) as u32)
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        self.ram.data[..buf.len()].clone_from_slice(buf);
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+#[test]
+fn test_register_select_bit() {
+    let mut mbc = Mbc2::new(vec![0; 0x4_0000]);
+
+    // Bit 8 set selects the ROM bank
+    mbc.write(Address(0x2100), 0x05).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x1_4000);
+    assert!(mbc.write(Address(0xA000), 0x01).is_err());
+
+    // Bit 8 clear enables RAM, even in the upper register range
+    mbc.write(Address(0x2000), 0x0A).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x1_4000);
+    mbc.write(Address(0xA000), 0x01).unwrap();
+
+    mbc.write(Address(0x0100), 0x00).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x4000);
+}
+
+#[test]
+fn test_ram_nibbles() {
+    let mut mbc = Mbc2::new(vec![0; 0x8000]);
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+
+    mbc.write(Address(0xA000), 0x5A).unwrap();
+    assert_eq!(mbc.read(Address(0xA000)).unwrap(), 0xFA);
+    assert_eq!(mbc.get_sram()[0], 0x0A);
+
+    // Echoed every 512 bytes
+    assert_eq!(mbc.read(Address(0xA200)).unwrap(), 0xFA);
+    assert_eq!(mbc.read(Address(0xBE00)).unwrap(), 0xFA);
+}
@@ -0,0 +1,376 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{header_has_battery, Mbc, MbcKind};
+use crate::error::ExecutionError;
+use crate::mem::{
+    Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
+};
+
+const RNG_RAMCS: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
+const RNG_ROM_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x4000));
+const RNG_RAM_BANK_SELECT: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const RNG_LATCH_CLOCK: AddressRange = AddressRange(Address(0x6000), Address(0x8000));
+const MASK_ROM_BANK_SELECT: u8 = 0b0111_1111;
+
+const RAM_BANKS: usize = 4;
+const RTC_FIRST_REGISTER: usize = 0x08;
+const RTC_REGISTERS: usize = 5;
+// Five current registers and five latched registers as 32 bit words followed
+// by a 64 bit unix timestamp, the layout most other emulators save.
+const RTC_SAVE_LEN: usize = RTC_REGISTERS * 4 * 2 + 8;
+
+const DH_DAY_HIGH: u8 = 0b0000_0001;
+const DH_HALT: u8 = 0b0100_0000;
+const DH_CARRY: u8 = 0b1000_0000;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const DAY_COUNTER_LIMIT: u64 = 512;
+
+pub struct Mbc3 {
+    battery: bool,
+    ram_protected: bool,
+    rom: Vec<u8>,
+    rom_bank_select: usize,
+    ram_bank_select: usize,
+    // Banked RAM with the RTC save block appended so get_sram covers both
+    ram: Ram,
+    rtc: Rtc,
+    latch_armed: bool,
+    clock: fn() -> u64,
+}
+
+// The clock is stored as a second count at `timestamp` and advanced lazily
+// from the wall clock whenever it's observed.
+struct Rtc {
+    counter: u64,
+    timestamp: u64,
+    halted: bool,
+    carry: bool,
+    latched: [u8; RTC_REGISTERS],
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>) -> Mbc3 {
+        Mbc3::with_clock(rom, unix_time)
+    }
+
+    pub fn with_clock(rom: Vec<u8>, clock: fn() -> u64) -> Mbc3 {
+        let mut mbc = Mbc3 {
+            battery: header_has_battery(&rom),
+            ram_protected: true,
+            rom,
+            rom_bank_select: 1,
+            ram_bank_select: 0,
+            ram: Ram::new(RNG_EXT_RAM.len() * RAM_BANKS + RTC_SAVE_LEN),
+            rtc: Rtc {
+                counter: 0,
+                timestamp: clock(),
+                halted: false,
+                carry: false,
+                latched: [0; RTC_REGISTERS],
+            },
+            latch_armed: false,
+            clock,
+        };
+        mbc.store_rtc();
+        mbc
+    }
+
+    fn map_address_into_ram(&self, a: Address) -> Address {
+        Address(((a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * self.ram_bank_select) as u16)
+    }
+
+    fn selected_rtc_register(&self) -> Option<usize> {
+        if is_rtc_register(self.ram_bank_select) {
+            Some(self.ram_bank_select - RTC_FIRST_REGISTER)
+        } else {
+            None
+        }
+    }
+
+    fn store_rtc(&mut self) {
+        let current = self.rtc.registers(self.rtc.timestamp);
+        let start = RNG_EXT_RAM.len() * RAM_BANKS;
+        let save = &mut self.ram.data[start..];
+        for (i, r) in current.iter().chain(self.rtc.latched.iter()).enumerate() {
+            save[i * 4..i * 4 + 4].copy_from_slice(&u32::from(*r).to_le_bytes());
+        }
+        save[RTC_REGISTERS * 8..].copy_from_slice(&self.rtc.timestamp.to_le_bytes());
+    }
+
+    fn load_rtc(&mut self) {
+        let start = RNG_EXT_RAM.len() * RAM_BANKS;
+        let save = &self.ram.data[start..];
+        // Every register fits in the low byte of its little endian word
+        let word = |i: usize| save[i * 4];
+        let mut current = [0; RTC_REGISTERS];
+        for (i, r) in current.iter_mut().enumerate() {
+            *r = word(i);
+        }
+        for (i, r) in self.rtc.latched.iter_mut().enumerate() {
+            *r = word(RTC_REGISTERS + i);
+        }
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&save[RTC_REGISTERS * 8..]);
+        self.rtc
+            .set_registers(current, u64::from_le_bytes(timestamp));
+    }
+}
+
+impl Rtc {
+    fn counter_at(&self, now: u64) -> u64 {
+        if self.halted {
+            self.counter
+        } else {
+            self.counter + now.saturating_sub(self.timestamp)
+        }
+    }
+
+    fn registers(&self, now: u64) -> [u8; RTC_REGISTERS] {
+        let counter = self.counter_at(now);
+        let days = counter / SECONDS_PER_DAY;
+        let mut dh = ((days >> 8) as u8) & DH_DAY_HIGH;
+        if self.halted {
+            dh |= DH_HALT;
+        }
+        if self.carry || days >= DAY_COUNTER_LIMIT {
+            dh |= DH_CARRY;
+        }
+        [
+            (counter % 60) as u8,
+            (counter / 60 % 60) as u8,
+            (counter / 3600 % 24) as u8,
+            days as u8,
+            dh,
+        ]
+    }
+
+    fn set_registers(&mut self, r: [u8; RTC_REGISTERS], now: u64) {
+        let days = u64::from(r[3]) | u64::from(r[4] & DH_DAY_HIGH) << 8;
+        self.counter = u64::from(r[0])
+            + u64::from(r[1]) * 60
+            + u64::from(r[2]) * 3600
+            + days * SECONDS_PER_DAY;
+        self.halted = r[4] & DH_HALT != 0;
+        self.carry = r[4] & DH_CARRY != 0;
+        self.timestamp = now;
+    }
+
+    fn write(&mut self, register: usize, v: u8, now: u64) {
+        let mut r = self.registers(now);
+        r[register] = v;
+        self.set_registers(r, now);
+    }
+
+    fn latch(&mut self, now: u64) {
+        self.latched = self.registers(now);
+    }
+}
+
+impl MemDevice for Mbc3 {
+    fn read(&self, a: Address) -> Result<u8, ExecutionError> {
+        if a.in_(RNG_ROM_BANK1) {
+            let index = self.map_address_into_rom(a).0 as usize;
+            Ok(self.rom[index])
+        } else if a.in_(RNG_EXT_RAM) {
+            match self.selected_rtc_register() {
+                Some(r) => Ok(self.rtc.latched[r]),
+                None => self.ram.read(self.map_address_into_ram(a)),
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    fn write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
+        if a.in_(RNG_EXT_RAM) {
+            if self.ram_protected {
+                error!(Cart, "Error: RAM is not writable right now");
+                Err(ExecutionError::ProtectionFault)
+            } else if let Some(r) = self.selected_rtc_register() {
+                self.rtc.write(r, v, (self.clock)());
+                self.store_rtc();
+                Ok(())
+            } else {
+                let mapped = self.map_address_into_ram(a);
+                self.ram.write(mapped, v)
+            }
+        } else if a.in_(RNG_RAMCS) {
+            self.ram_protected = v != 0x0A;
+            Ok(())
+        } else if a.in_(RNG_ROM_BANK_SELECT) {
+            self.rom_bank_select = (v & MASK_ROM_BANK_SELECT) as usize;
+            if self.rom_bank_select == 0 {
+                self.rom_bank_select = 1;
+            }
+            Ok(())
+        } else if a.in_(RNG_RAM_BANK_SELECT) {
+            let bank = v as usize;
+            if bank < RAM_BANKS || is_rtc_register(bank) {
+                self.ram_bank_select = bank;
+                Ok(())
+            } else {
+                error!(Cart, "Invalid MBC3 RAM bank {:#X}", v);
+                Err(ExecutionError::BusError)
+            }
+        } else if a.in_(RNG_LATCH_CLOCK) {
+            if self.latch_armed && v == 0x01 {
+                self.rtc.latch((self.clock)());
+                self.store_rtc();
+            }
+            self.latch_armed = v == 0x00;
+            Ok(())
+        } else {
+            error!(Cart, "Unimplemented MBC3 register {}", a);
+            Err(ExecutionError::BusError)
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc3
+    }
+
+    fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
+        let index = RNG_ROM_BANK1.len() * (self.rom_bank_select - 1) + a.0 as usize;
+        ExtendedAddress((index % self.rom.len()) as u32)
+    }
+
+    fn get_sram(&self) -> &[u8] {
+        self.ram.data.as_slice()
+    }
+
+    fn set_sram(&mut self, buf: &[u8]) {
+        self.ram.data[..buf.len()].clone_from_slice(buf);
+        if buf.len() == self.ram.data.len() {
+            self.load_rtc();
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+fn is_rtc_register(bank: usize) -> bool {
+    (RTC_FIRST_REGISTER..RTC_FIRST_REGISTER + RTC_REGISTERS).contains(&bank)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_TIME: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+fn advance_test_time(seconds: u64) {
+    TEST_TIME.with(|t| t.set(t.get() + seconds));
+}
+
+#[cfg(test)]
+fn make_test_mbc3() -> Mbc3 {
+    let mut mbc = Mbc3::with_clock(vec![0; 0x8000], || TEST_TIME.with(|t| t.get()));
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc
+}
+
+#[cfg(test)]
+fn read_rtc(mbc: &mut Mbc3, register: u8) -> u8 {
+    mbc.write(Address(0x4000), register).unwrap();
+    mbc.read(Address(0xA000)).unwrap()
+}
+
+#[cfg(test)]
+fn write_rtc(mbc: &mut Mbc3, register: u8, v: u8) {
+    mbc.write(Address(0x4000), register).unwrap();
+    mbc.write(Address(0xA000), v).unwrap();
+}
+
+#[test]
+fn test_rtc_latch() {
+    TEST_TIME.with(|t| t.set(1_000_000));
+    let mut mbc = make_test_mbc3();
+
+    advance_test_time(3 * 3600 + 2 * 60 + 1);
+    assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+
+    // A lone 0x01 write doesn't latch
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x08), 1);
+    assert_eq!(read_rtc(&mut mbc, 0x09), 2);
+    assert_eq!(read_rtc(&mut mbc, 0x0A), 3);
+
+    // Latched values hold until the next latch
+    advance_test_time(10);
+    assert_eq!(read_rtc(&mut mbc, 0x08), 1);
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x08), 11);
+}
+
+#[test]
+fn test_rtc_day_counter_overflow() {
+    TEST_TIME.with(|t| t.set(2_000_000));
+    let mut mbc = make_test_mbc3();
+    write_rtc(&mut mbc, 0x0B, 0xFF);
+    write_rtc(&mut mbc, 0x0C, DH_DAY_HIGH);
+
+    advance_test_time(SECONDS_PER_DAY - 1);
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x0B), 0xFF);
+    assert_eq!(read_rtc(&mut mbc, 0x0C), DH_DAY_HIGH);
+
+    advance_test_time(1);
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x0B), 0x00);
+    assert_eq!(read_rtc(&mut mbc, 0x0C), DH_CARRY);
+
+    // The carry stays set until it's cleared by the game
+    write_rtc(&mut mbc, 0x0C, 0);
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x0C), 0);
+}
+
+#[test]
+fn test_rtc_survives_sram_restore() {
+    TEST_TIME.with(|t| t.set(3_000_000));
+    let mut mbc = make_test_mbc3();
+    write_rtc(&mut mbc, 0x09, 30);
+    mbc.write(Address(0x4000), 0x01).unwrap();
+    mbc.write(Address(0xA000), 0x42).unwrap();
+    let sram = mbc.get_sram().to_vec();
+
+    advance_test_time(60);
+    let mut restored = make_test_mbc3();
+    restored.set_sram(&sram);
+    restored.write(Address(0x6000), 0x00).unwrap();
+    restored.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut restored, 0x09), 31);
+    restored.write(Address(0x4000), 0x01).unwrap();
+    assert_eq!(restored.read(Address(0xA000)).unwrap(), 0x42);
+}
+
+#[test]
+fn test_rtc_halt() {
+    TEST_TIME.with(|t| t.set(4_000_000));
+    let mut mbc = make_test_mbc3();
+    write_rtc(&mut mbc, 0x0C, DH_HALT);
+    advance_test_time(100);
+    mbc.write(Address(0x6000), 0x00).unwrap();
+    mbc.write(Address(0x6000), 0x01).unwrap();
+    assert_eq!(read_rtc(&mut mbc, 0x08), 0);
+    assert_eq!(read_rtc(&mut mbc, 0x0C), DH_HALT);
+}
@@ -1,6 +1,4 @@
-use log::error;
-
-use super::Mbc;
+use super::{header_has_battery, Mbc, MbcKind};
 use crate::error::ExecutionError;
 use crate::mem::{
     Address, AddressRange, ExtendedAddress, MemDevice, Ram, RNG_EXT_RAM, RNG_ROM_BANK1,
@@ -10,8 +8,11 @@ const RNG_RAMG: AddressRange = AddressRange(Address(0x0000), Address(0x2000));
 const RNG_LOWER_BANK_SELECT: AddressRange = AddressRange(Address(0x2000), Address(0x3000));
 const RNG_UPPER_BANK_SELECT: AddressRange = AddressRange(Address(0x3000), Address(0x4000));
 const RNG_RAMB: AddressRange = AddressRange(Address(0x4000), Address(0x6000));
+const MASK_UPPER_BANK_SELECT: usize = 0b1_0000_0000;
+const MASK_LOWER_BANK_SELECT: usize = 0b0_1111_1111;
 
 pub struct Mbc5 {
+    battery: bool,
     ram_protected: bool,
     rom: Vec<u8>,
     rom_bank_select: usize,
@@ -22,6 +23,7 @@ pub struct Mbc5 {
 impl Mbc5 {
     pub fn new(rom: Vec<u8>) -> Mbc5 {
         Mbc5 {
+            battery: header_has_battery(&rom),
             ram_protected: true,
             rom,
             rom_bank_select: 1,
@@ -37,7 +39,7 @@ impl MemDevice for Mbc5 {
             let index = self.map_address_into_rom(a).0 as usize;
             Ok(self.rom[index])
         } else if a.in_(RNG_EXT_RAM) {
-            self.ram.read(ram_bank_adjust(a, self.ram_bank_select))
+            Ok(self.ram.data[ram_index(a, self.ram_bank_select)])
         } else {
             unreachable!();
         }
@@ -45,36 +47,37 @@ impl MemDevice for Mbc5 {
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
         if a.in_(RNG_EXT_RAM) {
-            self.ram.write(ram_bank_adjust(a, self.ram_bank_select), v)
+            self.ram.data[ram_index(a, self.ram_bank_select)] = v;
+            Ok(())
         } else if a.in_(RNG_RAMG) {
             self.ram_protected = v != 0x0A;
             Ok(())
         } else if a.in_(RNG_UPPER_BANK_SELECT) {
-            self.rom_bank_select =
-                ((v as usize) << 8 & 0b11) | (self.rom_bank_select & !(0b11_0000_0000));
-            if self.rom_bank_select == 0 {
-                self.rom_bank_select = 1;
-            }
+            // Unlike MBC1, bank 0 can be mapped here
+            self.rom_bank_select = ((v as usize) << 8 & MASK_UPPER_BANK_SELECT)
+                | (self.rom_bank_select & MASK_LOWER_BANK_SELECT);
             Ok(())
         } else if a.in_(RNG_LOWER_BANK_SELECT) {
-            self.rom_bank_select = (v as usize) | (self.rom_bank_select & !(0b1111_1111));
-            if self.rom_bank_select == 0 {
-                self.rom_bank_select = 1;
-            }
+            self.rom_bank_select = (v as usize) | (self.rom_bank_select & MASK_UPPER_BANK_SELECT);
             Ok(())
         } else if a.in_(RNG_RAMB) {
             self.ram_bank_select = (v & 0b1111) as usize;
             Ok(())
         } else {
-            error!("Unimplemented MBC5 register {}", a);
+            error!(Cart, "Unimplemented MBC5 register {}", a);
             Err(ExecutionError::BusError)
         }
     }
 }
 
 impl Mbc for Mbc5 {
+    fn kind(&self) -> MbcKind {
+        MbcKind::Mbc5
+    }
+
     fn map_address_into_rom(&self, a: Address) -> ExtendedAddress {
-        ExtendedAddress((RNG_ROM_BANK1.len() * (self.rom_bank_select - 1)) as u32 + u32::from(a.0))
+        let index = RNG_ROM_BANK1.len() * self.rom_bank_select + (a - RNG_ROM_BANK1.0).0 as usize;
+        ExtendedAddress((index % self.rom.len()) as u32)
     }
 
     fn get_sram(&self) -> &[u8] {
@@ -84,8 +87,46 @@ impl Mbc for Mbc5 {
     fn set_sram(&mut self, buf: &[u8]) {
         self.ram.data[..buf.len()].clone_from_slice(buf);
     }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+fn ram_index(a: Address, bank: usize) -> usize {
+    (a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank
 }
 
-fn ram_bank_adjust(a: Address, bank: usize) -> Address {
-    Address(((a - RNG_EXT_RAM.0).0 as usize + RNG_EXT_RAM.len() * bank) as u16)
+#[test]
+fn test_rom_bank_switching() {
+    let mut mbc = Mbc5::new(vec![0; 0x80_0000]);
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x4000);
+
+    mbc.write(Address(0x2000), 0x00).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x0000);
+    assert_eq!(mbc.map_address_into_rom(Address(0x7FFF)).0, 0x3FFF);
+
+    mbc.write(Address(0x3000), 0x01).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x100 * 0x4000);
+
+    mbc.write(Address(0x2000), 0x23).unwrap();
+    assert_eq!(
+        mbc.map_address_into_rom(Address(0x4123)).0,
+        0x123 * 0x4000 + 0x123
+    );
+
+    mbc.write(Address(0x3000), 0xFE).unwrap();
+    assert_eq!(mbc.map_address_into_rom(Address(0x4000)).0, 0x23 * 0x4000);
+}
+
+#[test]
+fn test_ram_banking() {
+    let mut mbc = Mbc5::new(vec![0; 0x8000]);
+    mbc.write(Address(0x0000), 0x0A).unwrap();
+    mbc.write(Address(0x4000), 0x0F).unwrap();
+    mbc.write(Address(0xBFFF), 0x42).unwrap();
+    assert_eq!(mbc.get_sram()[RNG_EXT_RAM.len() * 16 - 1], 0x42);
+
+    mbc.write(Address(0x4000), 0x00).unwrap();
+    assert_eq!(mbc.read(Address(0xBFFF)).unwrap(), 0x00);
 }
@@ -1,4 +1,5 @@
 use crate::error::ExecutionError;
+use crate::rng::Rng;
 use std::convert::Into;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
@@ -30,6 +31,8 @@ pub const REG_HDMA4: Address = Address(0xFF54);
 pub const REG_HDMA5: Address = Address(0xFF55);
 pub const REG_RP: Address = Address(0xFF56);
 pub const REG_SVBK: Address = Address(0xFF70);
+pub const REG_PCM12: Address = Address(0xFF76);
+pub const REG_PCM34: Address = Address(0xFF77);
 pub const REG_SB: Address = Address(0xFF01);
 pub const REG_SC: Address = Address(0xFF02);
 pub const REG_DIV: Address = Address(0xFF04);
@@ -135,6 +138,10 @@ impl AddressRange {
     pub fn len(self) -> usize {
         (self.1 - self.0).into()
     }
+
+    pub fn is_empty(self) -> bool {
+        self.0 >= self.1
+    }
 }
 
 pub trait MemDevice {
@@ -146,12 +153,6 @@ pub trait MemDevice {
         self.write(a + Address(1), ((v >> 8) & 0xFF) as u8)?;
         Ok(())
     }
-
-    fn read16(&self, a: Address) -> Result<u16, ExecutionError> {
-        let hi = u16::from(self.read(a + Address(1))?);
-        let lo = u16::from(self.read(a)?);
-        Ok(hi << 8 | lo)
-    }
 }
 
 #[derive(Clone, Debug)]
@@ -165,6 +166,22 @@ impl Ram {
             data: vec![0; size],
         }
     }
+
+    pub fn fill(&mut self, fill: RamFill, rng: &mut Rng) {
+        match fill {
+            RamFill::Zero => self.data.iter_mut().for_each(|b| *b = 0),
+            RamFill::Ones => self.data.iter_mut().for_each(|b| *b = 0xFF),
+            RamFill::Random => self.data.iter_mut().for_each(|b| *b = rng.next_u8()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RamFill {
+    #[default]
+    Zero,
+    Ones,
+    Random,
 }
 
 impl MemDevice for Ram {
@@ -177,3 +194,77 @@ impl MemDevice for Ram {
         Ok(())
     }
 }
+
+pub fn register_name(a: Address) -> Option<&'static str> {
+    let name = match a.0 {
+        0xFF00 => "P1",
+        0xFF01 => "SB",
+        0xFF02 => "SC",
+        0xFF04 => "DIV",
+        0xFF05 => "TIMA",
+        0xFF06 => "TMA",
+        0xFF07 => "TAC",
+        0xFF0F => "IF",
+        0xFF10 => "NR10",
+        0xFF11 => "NR11",
+        0xFF12 => "NR12",
+        0xFF13 => "NR13",
+        0xFF14 => "NR14",
+        0xFF16 => "NR21",
+        0xFF17 => "NR22",
+        0xFF18 => "NR23",
+        0xFF19 => "NR24",
+        0xFF1A => "NR30",
+        0xFF1B => "NR31",
+        0xFF1C => "NR32",
+        0xFF1D => "NR33",
+        0xFF1E => "NR34",
+        0xFF20 => "NR41",
+        0xFF21 => "NR42",
+        0xFF22 => "NR43",
+        0xFF23 => "NR44",
+        0xFF24 => "NR50",
+        0xFF25 => "NR51",
+        0xFF26 => "NR52",
+        0xFF40 => "LCDC",
+        0xFF41 => "STAT",
+        0xFF42 => "SCY",
+        0xFF43 => "SCX",
+        0xFF44 => "LY",
+        0xFF45 => "LYC",
+        0xFF46 => "DMA",
+        0xFF47 => "BGP",
+        0xFF48 => "OBP0",
+        0xFF49 => "OBP1",
+        0xFF4A => "WY",
+        0xFF4B => "WX",
+        0xFF4D => "KEY1",
+        0xFF4F => "VBK",
+        0xFF51 => "HDMA1",
+        0xFF52 => "HDMA2",
+        0xFF53 => "HDMA3",
+        0xFF54 => "HDMA4",
+        0xFF55 => "HDMA5",
+        0xFF56 => "RP",
+        0xFF68 => "BCPS",
+        0xFF69 => "BCPD",
+        0xFF6A => "OCPS",
+        0xFF6B => "OCPD",
+        0xFF70 => "SVBK",
+        0xFF76 => "PCM12",
+        0xFF77 => "PCM34",
+        0xFFFF => "IE",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[test]
+fn test_register_name() {
+    assert_eq!(register_name(Address(0xFF41)), Some("STAT"));
+    assert_eq!(register_name(REG_INTR_FLAG), Some("IF"));
+    assert_eq!(register_name(Address(0xFF26)), Some("NR52"));
+    assert_eq!(register_name(REG_SVBK), Some("SVBK"));
+    assert_eq!(register_name(Address(0xFF15)), None);
+    assert_eq!(register_name(Address(0xC000)), None);
+}
@@ -1,17 +1,49 @@
-use std::collections::HashSet;
-
-use log::{error, info};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::alu::hi_lo;
 use crate::audio::{Audio, AudioSink};
 use crate::cart::Cart;
+use crate::cpu::Interrupt;
 use crate::error::ExecutionError;
 use crate::input::Input;
 use crate::lcd::Lcd;
 use crate::mem::*;
 use crate::mmu_exceptions::MmuExceptions;
+use crate::rng::Rng;
 use crate::timer::Timer;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpenBus {
+    Fixed(u8),
+    LastValue,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IoAccess {
+    Read(Address),
+    Write(Address, u8),
+}
+
+// Returning Some from a hook handles the access: the value is what a read returns, and a write
+// is swallowed. None lets the access continue to the hardware as normal.
+pub type IoHook = Box<dyn FnMut(IoAccess) -> Option<u8> + Send>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptEvent {
+    pub interrupt: Interrupt,
+    pub cycle: u64,
+    pub ly: u8,
+}
+
+// The oldest events are dropped once the log is full
+const INTERRUPT_LOG_CAPACITY: usize = 4096;
+
+const DMA_SOURCE_LIMIT: Address = Address(0xE000);
+
+// Only the low five bits of IF are backed; the rest always read as 1
+const IF_UNUSED_MASK: u8 = 0b1110_0000;
+
 pub struct Mmu {
     internal_ram: Ram,
     tiny_ram: Ram,
@@ -29,8 +61,13 @@ pub struct Mmu {
     pub timer: Timer,
     pub input: Input,
     pub pedantic: bool,
+    open_bus: OpenBus,
+    last_bus_value: Cell<u8>,
 
     pub watchpoints: HashSet<Address>,
+    io_hooks: RefCell<HashMap<Address, IoHook>>,
+    interrupt_log: Option<VecDeque<InterruptEvent>>,
+    frozen: HashMap<Address, u8>,
 
     exceptions: MmuExceptions,
 
@@ -39,9 +76,24 @@ pub struct Mmu {
     hdma3: u8,
     hdma4: u8,
     hdma5: u8,
+
+    pub rng: Rng,
+    // Kept so the RAM can be filled again when the seed changes
+    ram_fill: RamFill,
 }
 
 impl Mmu {
+    pub fn fill_ram(&mut self, fill: RamFill) {
+        self.ram_fill = fill;
+        self.internal_ram.fill(fill, &mut self.rng);
+        self.lcd.fill_ram(fill, &mut self.rng);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.fill_ram(self.ram_fill);
+    }
+
     pub fn new(cart: Cart, audio_sink: Box<dyn AudioSink + Send>, cgb_mode: bool) -> Mmu {
         Mmu {
             internal_ram: Ram::new(RNG_INT_RAM_0.len() * 8),
@@ -53,10 +105,12 @@ impl Mmu {
             exceptions: cart.get_mmu_exceptions(),
             cart,
             lcd: Box::new(Lcd::new(cgb_mode)),
-            audio: Audio::new(audio_sink),
+            audio: Audio::new(audio_sink, cgb_mode),
             timer: Timer::new(),
             input: Input::new(),
             pedantic: true,
+            open_bus: OpenBus::Fixed(0xFF),
+            last_bus_value: Cell::new(0xFF),
             ram_bank_select: 1,
 
             hdma1: 0,
@@ -65,37 +119,51 @@ impl Mmu {
             hdma4: 0,
             hdma5: 0,
 
+            rng: Rng::default(),
+            ram_fill: RamFill::default(),
+
             watchpoints: HashSet::new(),
+            io_hooks: RefCell::new(HashMap::new()),
+            interrupt_log: None,
+            frozen: HashMap::new(),
         }
     }
 
     fn dma(&mut self, mut src: Address) -> Result<(), ExecutionError> {
         // TODO: This should actually take 160us worth of cycles
+        // The DMA unit can't see IO or HRAM; pages E0-FF decode as work RAM instead
+        if src >= DMA_SOURCE_LIMIT {
+            src -= Address(0x2000);
+        }
         let mut dst = RNG_LCD_OAM.0;
-        while dst < RNG_LCD_OAM.1 {
-            let v = self.read(src)?;
-            self.write(dst, v)?;
+        let mut result = Ok(());
+        self.lcd.begin_bulk_vram();
+        while result.is_ok() && dst < RNG_LCD_OAM.1 {
+            result = self.read(src).and_then(|v| self.write(dst, v));
             dst += Address(1);
             src += Address(1);
         }
+        self.lcd.end_bulk_vram();
 
-        Ok(())
+        result
     }
 
     fn hdma(&mut self, src: AddressRange, mut dest: Address) -> Result<(), ExecutionError> {
         let mut src_cursor = src.0;
-        while src_cursor < src.1 {
-            let v = self.read(src_cursor)?;
-            self.write(dest, v)?;
+        let mut result = Ok(());
+        self.lcd.begin_bulk_vram();
+        while result.is_ok() && src_cursor < src.1 {
+            result = self.read(src_cursor).and_then(|v| self.write(dest, v));
             dest += Address(1);
             src_cursor += Address(1);
         }
-        Ok(())
+        self.lcd.end_bulk_vram();
+        result
     }
 
     fn _read(&self, a: Address) -> Result<u8, ExecutionError> {
         if self.watchpoints.contains(&a) {
-            info!("Read watchpoint for {:?}", a);
+            info!(Mmu, "Read watchpoint for {:?}", a);
             Err(ExecutionError::MmuException)
         } else if a == REG_SVBK {
             Ok(self.ram_bank_select as u8)
@@ -137,17 +205,18 @@ impl Mmu {
             || a.in_(RNG_LCD_OAM)
         {
             self.lcd.read(a)
-        } else if a.in_(RNG_SND_WAV_RAM) || a.in_(RNG_SND_REGS) {
+        } else if a.in_(RNG_SND_WAV_RAM) || a.in_(RNG_SND_REGS) || a == REG_PCM12 || a == REG_PCM34
+        {
             self.audio.read(a)
         } else {
             match a {
                 REG_INTR_ENABLE => Ok(self.interrupt_enable),
-                REG_INTR_FLAG => Ok(self.interrupt_flag),
+                REG_INTR_FLAG => Ok(self.interrupt_flag | IF_UNUSED_MASK),
                 REG_TIMA | REG_DIV | REG_TAC | REG_TMA => self.timer.read(a),
                 REG_P1 => self.input.read(a),
                 REG_SB | REG_SC => Ok(0),
                 _ => {
-                    error!("MMU: Unimplemented memory read at address {:?}", a);
+                    error!(Mmu, "MMU: Unimplemented memory read at address {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
@@ -156,7 +225,7 @@ impl Mmu {
 
     fn _write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
         if self.watchpoints.contains(&a) {
-            info!("Write watchpoint for {:?}", a);
+            info!(Mmu, "Write watchpoint for {:?}", a);
             Err(ExecutionError::MmuException)
         } else if a == REG_RP {
             // IR not supported right now
@@ -220,20 +289,104 @@ impl Mmu {
                     Ok(())
                 }
                 REG_INTR_FLAG => {
-                    self.interrupt_flag = v;
+                    self.interrupt_flag = v & !IF_UNUSED_MASK;
                     Ok(())
                 }
                 REG_TIMA | REG_DIV | REG_TAC | REG_TMA => self.timer.write(a, v),
                 REG_P1 => self.input.write(a, v),
                 REG_SB | REG_SC => Ok(()),
                 _ => {
-                    error!("MMU: Unimplemented memory write at address {:?}", a);
+                    error!(Mmu, "MMU: Unimplemented memory write at address {:?}", a);
                     Err(ExecutionError::BusError)
                 }
             }
         }
     }
 
+    pub fn io_registers(&self) -> [u8; 0x80] {
+        let mut registers = [0xFF; 0x80];
+        for (i, r) in registers.iter_mut().enumerate() {
+            if let Ok(v) = self._read(REG_P1 + Address(i as u16)) {
+                *r = v;
+            }
+        }
+        registers
+    }
+
+    pub fn set_interrupt_log(&mut self, enabled: bool) {
+        self.interrupt_log = if enabled { Some(VecDeque::new()) } else { None };
+    }
+
+    pub fn drain_interrupt_log(&mut self) -> Vec<InterruptEvent> {
+        match self.interrupt_log {
+            Some(ref mut log) => log.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn log_interrupt_requests(&mut self, if_: u8, cycle: u64) {
+        let ly = self.lcd.ly();
+        if let Some(ref mut log) = self.interrupt_log {
+            for bit in 0..5 {
+                if if_ & (1 << bit) == 0 {
+                    continue;
+                }
+
+                if log.len() == INTERRUPT_LOG_CAPACITY {
+                    log.pop_front();
+                }
+                log.push_back(InterruptEvent {
+                    interrupt: Interrupt::from_bits(1 << bit),
+                    cycle,
+                    ly,
+                });
+            }
+        }
+    }
+
+    // Every later write to a frozen address stores the frozen value instead. Nothing is frozen
+    // if the initial write fails.
+    pub fn freeze(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
+        self.write(a, v)?;
+        self.frozen.insert(a, v);
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, a: Address) {
+        self.frozen.remove(&a);
+    }
+
+    pub fn set_io_hook(&mut self, a: Address, hook: IoHook) {
+        self.io_hooks.get_mut().insert(a, hook);
+    }
+
+    pub fn remove_io_hook(&mut self, a: Address) {
+        self.io_hooks.get_mut().remove(&a);
+    }
+
+    fn run_io_hook(&self, access: IoAccess) -> Option<u8> {
+        let mut hooks = self.io_hooks.borrow_mut();
+        if hooks.is_empty() {
+            return None;
+        }
+
+        let a = match access {
+            IoAccess::Read(a) | IoAccess::Write(a, _) => a,
+        };
+        hooks.get_mut(&a).and_then(|hook| hook(access))
+    }
+
+    pub fn set_open_bus_behavior(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    fn open_bus_value(&self) -> u8 {
+        match self.open_bus {
+            OpenBus::Fixed(v) => v,
+            OpenBus::LastValue => self.last_bus_value.get(),
+        }
+    }
+
     pub fn toggle_double_speed(&mut self) {
         self.double_speed_mode = !self.double_speed_mode;
         self.timer.toggle_double_speed();
@@ -242,15 +395,23 @@ impl Mmu {
 
 impl MemDevice for Mmu {
     fn read(&self, a: Address) -> Result<u8, ExecutionError> {
-        if self.pedantic && !self.exceptions.allow(a) {
-            self._read(a)
+        let v = if let Some(v) = self.run_io_hook(IoAccess::Read(a)) {
+            v
+        } else if self.pedantic && !self.exceptions.allow(a) {
+            self._read(a)?
         } else {
-            self._read(a).or(Ok(0))
-        }
+            self._read(a).unwrap_or_else(|_| self.open_bus_value())
+        };
+        self.last_bus_value.set(v);
+        Ok(v)
     }
 
     fn write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
-        if self.pedantic && !self.exceptions.allow(a) {
+        self.last_bus_value.set(v);
+        let v = self.frozen.get(&a).copied().unwrap_or(v);
+        if self.run_io_hook(IoAccess::Write(a, v)).is_some() {
+            Ok(())
+        } else if self.pedantic && !self.exceptions.allow(a) {
             self._write(a, v)
         } else {
             self._write(a, v).or(Ok(()))
@@ -263,3 +424,170 @@ fn ram_bank_adjust(a: Address, bank: usize) -> Address {
         RNG_INT_RAM_1.len() * if bank > 0 { bank - 1 } else { 0 } + RNG_INT_RAM_0.len();
     (a - RNG_INT_RAM_1.0) + Address(bank_offset as u16)
 }
+
+#[cfg(test)]
+fn make_test_mmu() -> Mmu {
+    use crate::audio::NullSink;
+    use crate::cart::LoadOptions;
+    use std::io::Cursor;
+
+    let cart = Cart::load(Cursor::new(vec![0; 0x8000]), &LoadOptions::default()).unwrap();
+    Mmu::new(cart, Box::new(NullSink), false)
+}
+
+#[test]
+fn test_open_bus() {
+    let mut mmu = make_test_mmu();
+    mmu.pedantic = false;
+    let unmapped = Address(0xFEA0);
+
+    assert_eq!(mmu.read(unmapped).unwrap(), 0xFF);
+
+    mmu.set_open_bus_behavior(OpenBus::LastValue);
+    mmu.write(Address(0xC000), 0x5A).unwrap();
+    assert_eq!(mmu.read(unmapped).unwrap(), 0x5A);
+
+    mmu.set_open_bus_behavior(OpenBus::Fixed(0x00));
+    assert_eq!(mmu.read(unmapped).unwrap(), 0x00);
+}
+
+#[test]
+fn test_io_registers() {
+    let mut mmu = make_test_mmu();
+    mmu.write(Address(0xFF41), 0b0100_0000).unwrap();
+    mmu.write(Address(0xFF42), 0x12).unwrap();
+
+    let registers = mmu.io_registers();
+
+    assert_eq!(registers[0x40], 0x83); // LCDC
+    assert_eq!(registers[0x41] & 0b1111_1000, 0b0100_0000); // STAT
+    assert_eq!(registers[0x42], 0x12); // SCY
+    assert_eq!(registers[0x44], 0); // LY
+    assert_eq!(registers[0x03], 0xFF); // Unmapped
+}
+
+#[test]
+fn test_interrupt_registers() {
+    let mut mmu = make_test_mmu();
+
+    mmu.write(REG_INTR_FLAG, 0b0000_0101).unwrap();
+    mmu.write(REG_INTR_ENABLE, 0b0001_0011).unwrap();
+    assert_eq!(mmu.read(REG_INTR_FLAG).unwrap(), 0b1110_0101);
+    assert_eq!(mmu.read(REG_INTR_ENABLE).unwrap(), 0b0001_0011);
+    assert_eq!(mmu.interrupt_flag, 0b0000_0101);
+
+    mmu.write(REG_INTR_FLAG, 0xFF).unwrap();
+    assert_eq!(mmu.interrupt_flag, 0b0001_1111);
+    assert_eq!(mmu.read(REG_INTR_FLAG).unwrap(), 0xFF);
+}
+
+#[test]
+fn test_io_hook() {
+    let mut mmu = make_test_mmu();
+    let writes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let hook_writes = writes.clone();
+
+    mmu.set_io_hook(
+        REG_P1,
+        Box::new(move |access| match access {
+            IoAccess::Read(_) => Some(0x2E),
+            IoAccess::Write(_, v) => {
+                hook_writes.lock().unwrap().push(v);
+                None
+            }
+        }),
+    );
+
+    assert_eq!(mmu.read(REG_P1).unwrap(), 0x2E);
+    mmu.write(REG_P1, 0x10).unwrap();
+    assert_eq!(*writes.lock().unwrap(), vec![0x10]);
+    assert_eq!(mmu.input.read(REG_P1).unwrap() & 0x30, 0x10);
+
+    mmu.remove_io_hook(REG_P1);
+    assert_eq!(mmu.read(REG_P1).unwrap() & 0x0F, 0x0F);
+}
+
+#[test]
+fn test_freeze_failed_write() {
+    let mut mmu = make_test_mmu();
+    mmu.pedantic = true;
+
+    assert!(mmu.freeze(Address(0xFF03), 0x12).is_err());
+    assert!(mmu.frozen.is_empty());
+
+    mmu.freeze(Address(0xC000), 0x12).unwrap();
+    assert_eq!(mmu.frozen.get(&Address(0xC000)), Some(&0x12));
+}
+
+#[test]
+fn test_hram_separate_from_work_ram() {
+    let mut mmu = make_test_mmu();
+
+    mmu.write(Address(0xC000), 0x11).unwrap();
+    mmu.write(Address(0xD000), 0x22).unwrap();
+    mmu.write(Address(0xFF80), 0x33).unwrap();
+    mmu.write(Address(0xFFFE), 0x44).unwrap();
+
+    assert_eq!(mmu.read(Address(0xC000)).unwrap(), 0x11);
+    assert_eq!(mmu.read(Address(0xD000)).unwrap(), 0x22);
+    assert_eq!(mmu.read(Address(0xFF80)).unwrap(), 0x33);
+    assert_eq!(mmu.read(Address(0xFFFE)).unwrap(), 0x44);
+
+    // Switching work RAM banks leaves HRAM alone
+    mmu.write(REG_SVBK, 2).unwrap();
+    assert_eq!(mmu.read(Address(0xD000)).unwrap(), 0x00);
+    assert_eq!(mmu.read(Address(0xFF80)).unwrap(), 0x33);
+    assert_eq!(mmu.read(Address(0xFFFE)).unwrap(), 0x44);
+}
+
+#[test]
+fn test_dma_source() {
+    let mut mmu = make_test_mmu();
+    for i in 0..0xA0 {
+        mmu.write(Address(0x8000 + i), i as u8).unwrap();
+        mmu.write(Address(0xC100 + i), !(i as u8)).unwrap();
+    }
+
+    mmu.write(REG_DMA, 0x80).unwrap();
+    for i in 0..0xA0 {
+        assert_eq!(mmu.read(RNG_LCD_OAM.0 + Address(i)).unwrap(), i as u8);
+    }
+
+    mmu.write(REG_DMA, 0xE1).unwrap();
+    for i in 0..0xA0 {
+        assert_eq!(mmu.read(RNG_LCD_OAM.0 + Address(i)).unwrap(), !(i as u8));
+    }
+}
+
+#[test]
+fn test_dma_from_work_ram() {
+    let mut mmu = make_test_mmu();
+    mmu.write(Address(0xFF40), 0x83).unwrap(); // LCD, objects and background on
+    mmu.write(Address(0xFF48), 0xE4).unwrap();
+    for a in 0x8010..0x8020 {
+        mmu.write(Address(a), 0xFF).unwrap(); // Tile 1 is solid color 3
+    }
+    for i in 0..0xA0 {
+        mmu.write(Address(0xC000 + i), 0).unwrap();
+    }
+    // One sprite in the top left corner
+    for (i, v) in [16, 8, 1, 0].iter().enumerate() {
+        mmu.write(Address(0xC000 + i as u16), *v).unwrap();
+    }
+    assert_eq!(
+        mmu.lcd.render_scanline(0)[0],
+        crate::lcd::fb::DMG_COLOR_WHITE
+    );
+
+    mmu.write(REG_DMA, 0xC0).unwrap();
+    for i in 0..0xA0 {
+        assert_eq!(
+            mmu.read(RNG_LCD_OAM.0 + Address(i)).unwrap(),
+            mmu.read(Address(0xC000 + i)).unwrap()
+        );
+    }
+    assert_eq!(
+        mmu.lcd.render_scanline(0)[0],
+        crate::lcd::fb::DMG_COLOR_BLACK
+    );
+}
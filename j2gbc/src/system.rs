@@ -1,14 +1,41 @@
 use std::io::Read;
-use std::time::Duration;
-
-use log::info;
+use std::time::{Duration, Instant};
 
 use crate::{
-    audio::AudioSink, cart::Cart, cpu::Cpu, debug::Debugger, input::Button, lcd::fb::Framebuffer,
+    audio::{AudioSink, TurboAudio},
+    cart::{Cart, CartHeader, LoadOptions, LoadWarning},
+    cpu::{BreakReason, Cpu},
+    debug::Debugger,
+    error::ExecutionError,
+    input::Button,
+    lcd::{
+        fb::{Framebuffer, Pixel},
+        CvdType, Rotation,
+    },
+    mem::Address,
+    mmu::OpenBus,
+    rng::Rng,
 };
 
+mod governor;
+pub mod replay;
+
+pub use self::governor::{Governor, GovernorAction};
+
+// How much faster than real time the system runs while turbo is held, unless changed
+const DEFAULT_TURBO_SPEED: u32 = 4;
+
 pub struct System {
     cpu: Cpu,
+    turbo: bool,
+    // None runs as fast as the host allows
+    turbo_speed: Option<u32>,
+    turbo_audio: TurboAudio,
+}
+
+pub struct FrameOutput<'a> {
+    pub framebuffer: &'a Framebuffer,
+    pub audio: &'a [(f32, f32)],
 }
 
 impl System {
@@ -17,31 +44,231 @@ impl System {
         audio_sink: Box<dyn AudioSink + Send>,
         allow_cgb_mode: bool,
     ) -> std::io::Result<System> {
-        let c = Cart::load(cart_data)?;
+        System::new_with_options(
+            cart_data,
+            audio_sink,
+            allow_cgb_mode,
+            &LoadOptions::default(),
+        )
+    }
+
+    pub fn new_with_options<R: Read>(
+        cart_data: R,
+        audio_sink: Box<dyn AudioSink + Send>,
+        allow_cgb_mode: bool,
+        options: &LoadOptions,
+    ) -> std::io::Result<System> {
+        let c = Cart::load(cart_data, options)?;
+
+        info!(System, "Name: {}", c.name());
+        info!(System, "File Size: {} bytes", c.data.len());
+        info!(System, "Cart type: {}", c.type_());
+        info!(System, "MBC: {:?}", c.mbc_kind());
+        match c.rom_size() {
+            Some(size) => info!(System, "ROM Size: {} bytes", size),
+            None => warn!(System, "Unknown ROM size code in the header"),
+        }
+        match c.ram_size() {
+            Some(size) => info!(System, "RAM Size: {} bytes", size),
+            None => warn!(System, "Unknown RAM size code in the header"),
+        }
 
-        info!("Name: {}", c.name());
-        info!("File Size: {} bytes", c.data.len());
-        info!("Cart type: {}", c.type_());
-        info!("ROM Size: {} bytes", c.rom_size());
-        info!("RAM Size: {} bytes", c.ram_size());
+        let mut cpu = Cpu::new(c, audio_sink, allow_cgb_mode);
+        cpu.mmu.rng = Rng::new(options.seed);
+        cpu.mmu.fill_ram(options.ram_fill);
 
-        let cpu = Cpu::new(c, audio_sink, allow_cgb_mode);
+        Ok(System {
+            cpu,
+            turbo: false,
+            turbo_speed: Some(DEFAULT_TURBO_SPEED),
+            turbo_audio: TurboAudio::Resample,
+        })
+    }
+
+    pub fn run_for_duration(&mut self, duration: &Duration) -> Vec<BreakReason> {
+        if !self.turbo {
+            return self.cpu.run_for_duration(duration);
+        }
+
+        match self.turbo_speed {
+            Some(speed) => self.cpu.run_for_duration(&(*duration * speed)),
+            None => self.run_uncapped(duration),
+        }
+    }
 
-        Ok(System { cpu })
+    // Emulates a frame's worth of time at a time until `duration` of real time has passed
+    fn run_uncapped(&mut self, duration: &Duration) -> Vec<BreakReason> {
+        let start = Instant::now();
+        let chunk = Governor::default().target();
+        let mut reasons = Vec::new();
+        loop {
+            reasons.extend(self.cpu.run_for_duration(&chunk));
+            if self.cpu.debug_halted || start.elapsed() >= *duration {
+                return reasons;
+            }
+        }
     }
 
-    pub fn run_for_duration(&mut self, duration: &Duration) {
-        self.cpu.run_for_duration(duration);
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.update_turbo_audio();
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    pub fn set_turbo_speed(&mut self, speed: Option<u32>) {
+        self.turbo_speed = speed.map(|s| s.max(1));
+        self.update_turbo_audio();
+    }
+
+    pub fn turbo_speed(&self) -> Option<u32> {
+        self.turbo_speed
+    }
+
+    pub fn set_turbo_audio(&mut self, turbo_audio: TurboAudio) {
+        self.turbo_audio = turbo_audio;
+        self.update_turbo_audio();
+    }
+
+    // Uncapped has no fixed rate to resample by, so next to nothing reaches the sink
+    fn update_turbo_audio(&mut self) {
+        let speedup = match (self.turbo, self.turbo_speed) {
+            (false, _) => 1,
+            (true, Some(speed)) => u64::from(speed),
+            (true, None) => u64::MAX,
+        };
+        self.cpu
+            .mmu
+            .audio
+            .synth
+            .set_speedup(speedup, self.turbo_audio);
+    }
+
+    pub fn advance_frame(&mut self) -> FrameOutput<'_> {
+        self.cpu.mmu.audio.synth.start_sample_capture();
+        self.cpu.run_for_frame();
+
+        let mmu = &mut self.cpu.mmu;
+        FrameOutput {
+            framebuffer: mmu.lcd.get_framebuffer(),
+            audio: mmu.audio.synth.stop_sample_capture(),
+        }
+    }
+
+    pub fn start_capture(&mut self, frame_skip: usize, max_frames: usize) {
+        self.cpu.mmu.lcd.start_capture(frame_skip, max_frames);
+    }
+
+    pub fn stop_capture(&mut self) -> Vec<Vec<u8>> {
+        self.cpu.mmu.lcd.stop_capture()
+    }
+
+    pub fn run_instructions(&mut self, max: u64) -> u64 {
+        self.cpu.run_instructions(max)
+    }
+
+    pub fn run_until_pc(&mut self, addr: Address, max_cycles: u64) -> Result<bool, ExecutionError> {
+        self.cpu.run_until_pc(addr, max_cycles)
+    }
+
+    pub fn profile_region(&mut self, start: Address, end: Address, max_cycles: u64) -> Option<u64> {
+        self.cpu.profile_region(start, end, max_cycles)
+    }
+
+    pub fn load_memory_image(&mut self, image: &[(Address, u8)]) -> Result<(), ExecutionError> {
+        let mut debugger = self.debugger();
+        for &(addr, v) in image {
+            debugger.write_mem(addr, v)?;
+        }
+        Ok(())
+    }
+
+    pub fn freeze_address(&mut self, addr: Address, v: u8) -> Result<(), ExecutionError> {
+        self.cpu.mmu.freeze(addr, v)
+    }
+
+    pub fn unfreeze_address(&mut self, addr: Address) {
+        self.cpu.mmu.unfreeze(addr);
+    }
+
+    pub fn program_counter(&self) -> Address {
+        self.cpu.pc
+    }
+
+    pub fn set_program_counter(&mut self, addr: Address) {
+        self.cpu.pc = addr;
+    }
+
+    pub fn stack_pointer(&self) -> Address {
+        self.cpu.sp
+    }
+
+    pub fn set_stack_pointer(&mut self, addr: Address) {
+        self.cpu.sp = addr;
+    }
+
+    pub fn step_over(&mut self, max_cycles: u64) -> Result<bool, ExecutionError> {
+        self.cpu.step_over(max_cycles)
     }
 
     pub fn get_framebuffer(&self) -> &Framebuffer {
         self.cpu.mmu.lcd.get_framebuffer()
     }
 
+    // Re-seeds the RNG and fills RAM again, using the fill from the load options
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.mmu.set_seed(seed);
+    }
+
+    pub fn set_output_transform(&mut self, rotation: Rotation, flip_h: bool, flip_v: bool) {
+        self.cpu
+            .mmu
+            .lcd
+            .set_output_transform(rotation, flip_h, flip_v);
+    }
+
+    pub fn output_size(&self) -> (usize, usize) {
+        self.cpu.mmu.lcd.output_size()
+    }
+
+    pub fn render_transformed(&self) -> Vec<Pixel> {
+        self.cpu.mmu.lcd.render_transformed()
+    }
+
+    pub fn set_cvd_filter(&mut self, filter: Option<CvdType>) {
+        self.cpu.mmu.lcd.set_cvd_filter(filter);
+    }
+
+    pub fn set_force_render(&mut self, force: bool) {
+        self.cpu.mmu.lcd.set_force_render(force);
+    }
+
+    pub fn set_bg_color0_transparent(&mut self, transparent: bool) {
+        self.cpu.mmu.lcd.set_bg_color0_transparent(transparent);
+    }
+
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.cpu.prefetch = prefetch;
+    }
+
     pub fn set_mmu_pedantic(&mut self, pedantic: bool) {
         self.cpu.mmu.pedantic = pedantic;
     }
 
+    pub fn set_open_bus_behavior(&mut self, open_bus: OpenBus) {
+        self.cpu.mmu.set_open_bus_behavior(open_bus);
+    }
+
+    pub fn reset_audio(&mut self) {
+        self.cpu.mmu.audio.reset_channels();
+    }
+
+    pub fn set_wave_trigger_ramp(&mut self, ramp: bool) {
+        self.cpu.mmu.audio.synth.chan3.set_trigger_ramp(ramp);
+    }
+
     pub fn load_cart_sram(&mut self, sram: &[u8]) {
         self.cpu.mmu.cart.set_sram(sram);
     }
@@ -50,6 +277,28 @@ impl System {
         self.cpu.mmu.cart.get_sram()
     }
 
+    pub fn cart_header(&self) -> Option<CartHeader> {
+        self.cpu.mmu.cart.header()
+    }
+
+    pub fn load_warnings(&self) -> &[LoadWarning] {
+        self.cpu.mmu.cart.load_warnings()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.cpu.mmu.cart.has_battery()
+    }
+
+    // Paces frames from advance_frame, so a front-end driven by it also runs faster in turbo
+    pub fn governor(&self) -> Governor {
+        let target = Governor::default().target();
+        match (self.turbo, self.turbo_speed) {
+            (false, _) => Governor::new(target),
+            (true, Some(speed)) => Governor::new(target / speed),
+            (true, None) => Governor::new(Duration::from_secs(0)),
+        }
+    }
+
     pub fn debugger(&mut self) -> Debugger {
         Debugger::new(&mut self.cpu)
     }
@@ -69,3 +318,524 @@ pub enum SystemMode {
     DMG,
     CGB,
 }
+
+#[cfg(test)]
+fn make_test_system(rom_patches: &[(usize, &[u8])]) -> System {
+    make_test_system_with_sink(rom_patches, Box::new(crate::audio::NullSink))
+}
+
+#[cfg(test)]
+fn make_test_system_with_sink(
+    rom_patches: &[(usize, &[u8])],
+    audio_sink: Box<dyn AudioSink + Send>,
+) -> System {
+    System::new(
+        std::io::Cursor::new(make_test_rom(rom_patches)),
+        audio_sink,
+        false,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+fn make_test_rom(rom_patches: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    for (offset, bytes) in rom_patches {
+        rom[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+    }
+    rom
+}
+
+#[test]
+fn test_step_over_call() {
+    use crate::cpu::Register8;
+
+    let mut system = make_test_system(&[
+        (0x100, &[0xCD, 0x50, 0x01]), // call $0150
+        (0x150, &[0x3E, 0x42, 0xC9]), // ld a,$42; ret
+    ]);
+
+    assert!(system.step_over(1000).unwrap());
+
+    assert_eq!(system.cpu.pc, Address(0x103));
+    assert_eq!(system.cpu.sp, Address(0xFFFE));
+    assert_eq!(system.cpu[Register8::A], 0x42);
+}
+
+#[test]
+fn test_step_over_call_never_returns() {
+    let mut system = make_test_system(&[
+        (0x100, &[0xCD, 0x50, 0x01]), // call $0150
+        (0x150, &[0x18, 0xFE]),       // jr -2
+    ]);
+
+    assert!(!system.step_over(1000).unwrap());
+    assert_eq!(system.cpu.pc, Address(0x150));
+    assert!(system.cpu.cycle() >= 1000);
+
+    // With IE clear nothing can wake the CPU, so the halt ends the step
+    let mut system = make_test_system(&[
+        (0x100, &[0xCD, 0x50, 0x01]), // call $0150
+        (0x150, &[0xF3, 0x76]),       // di; halt
+    ]);
+
+    assert!(!system.step_over(u64::MAX / 2).unwrap());
+    assert_eq!(system.cpu.pc, Address(0x152));
+}
+
+#[test]
+fn test_step_over_call_breakpoint() {
+    let mut system = make_test_system(&[
+        (0x100, &[0xCD, 0x50, 0x01]), // call $0150
+        (0x150, &[0x00, 0x00, 0xC9]), // nop; nop; ret
+    ]);
+    system.cpu.breakpoints.insert(Address(0x151));
+
+    assert!(!system.step_over(1000).unwrap());
+    assert_eq!(system.cpu.pc, Address(0x151));
+    assert!(system.cpu.debug_halted);
+}
+
+#[test]
+fn test_advance_frame() {
+    use crate::lcd::fb::SCREEN_SIZE;
+
+    struct TestSink;
+
+    impl AudioSink for TestSink {
+        fn emit_sample(&mut self, _: (f32, f32)) {}
+
+        fn sample_rate(&self) -> u64 {
+            48_000
+        }
+    }
+
+    let mut system = make_test_system_with_sink(
+        &[(0x100, &[0x18, 0xFE])], // jr -2
+        Box::new(TestSink),
+    );
+
+    let output = system.advance_frame();
+    assert_eq!(
+        output.framebuffer.raw().len(),
+        SCREEN_SIZE.0 * SCREEN_SIZE.1
+    );
+    assert!(!output.audio.is_empty());
+    assert_eq!(system.cpu.mmu.lcd.frame_count(), 1);
+}
+
+#[test]
+fn test_unsupported_mbc_fallback() {
+    use crate::audio::NullSink;
+    use std::io::Cursor;
+
+    let rom = make_test_rom(&[(0x147, &[0x22])]); // MBC7
+    let options = LoadOptions {
+        fallback_unsupported_mbc: true,
+        ..LoadOptions::default()
+    };
+
+    assert!(System::new(Cursor::new(rom.clone()), Box::new(NullSink), false).is_err());
+    assert!(
+        System::new_with_options(Cursor::new(rom), Box::new(NullSink), false, &options).is_ok()
+    );
+}
+
+#[test]
+fn test_ram_fill() {
+    use crate::audio::NullSink;
+    use crate::mem::{MemDevice, RamFill};
+    use std::io::Cursor;
+
+    let rom = make_test_rom(&[]);
+    let options = LoadOptions {
+        ram_fill: RamFill::Ones,
+        ..LoadOptions::default()
+    };
+
+    let system = System::new(Cursor::new(rom.clone()), Box::new(NullSink), false).unwrap();
+    assert_eq!(system.cpu.mmu.read(Address(0xC000)).unwrap(), 0x00);
+
+    let system =
+        System::new_with_options(Cursor::new(rom), Box::new(NullSink), false, &options).unwrap();
+    assert_eq!(system.cpu.mmu.read(Address(0xC000)).unwrap(), 0xFF);
+    assert_eq!(system.cpu.mmu.read(Address(0xDFFF)).unwrap(), 0xFF);
+    assert_eq!(system.cpu.mmu.read(Address(0x8000)).unwrap(), 0xFF);
+}
+
+#[test]
+fn test_seeded_ram_fill() {
+    use crate::audio::NullSink;
+    use crate::mem::{MemDevice, RamFill};
+    use std::io::Cursor;
+
+    let rom = make_test_rom(&[]);
+    let make_system = |seed| {
+        let options = LoadOptions {
+            ram_fill: RamFill::Random,
+            seed,
+            ..LoadOptions::default()
+        };
+        System::new_with_options(
+            Cursor::new(rom.clone()),
+            Box::new(NullSink),
+            false,
+            &options,
+        )
+        .unwrap()
+    };
+
+    let work_ram = |system: System| {
+        (0xC000..0xE000)
+            .map(|a| system.cpu.mmu.read(Address(a)).unwrap())
+            .collect::<Vec<u8>>()
+    };
+
+    let a = work_ram(make_system(7));
+    assert_eq!(a, work_ram(make_system(7)));
+    assert_ne!(a, work_ram(make_system(8)));
+    assert!(a.iter().any(|b| *b != 0));
+
+    let mut system = make_system(8);
+    system.set_seed(7);
+    assert_eq!(a, work_ram(system));
+}
+
+#[test]
+fn test_capture_frames() {
+    use crate::lcd::fb::SCREEN_SIZE;
+
+    let mut system = make_test_system(&[(0x100, &[0x18, 0xFE])]); // jr -2
+
+    system.start_capture(0, 100);
+    for _ in 0..3 {
+        system.advance_frame();
+    }
+    let frames = system.stop_capture();
+
+    assert_eq!(frames.len(), 3);
+    for frame in frames {
+        assert_eq!(frame.len(), SCREEN_SIZE.0 * SCREEN_SIZE.1 * 4);
+    }
+}
+
+#[test]
+fn test_run_instructions() {
+    let mut system = make_test_system(&[]);
+
+    assert_eq!(system.run_instructions(100), 100);
+    assert_eq!(system.cpu.pc, Address(0x100 + 100));
+
+    system.cpu.breakpoints.insert(Address(0x100 + 150));
+    assert_eq!(system.run_instructions(100), 50);
+    assert_eq!(system.cpu.pc, Address(0x100 + 150));
+
+    // Halted with nothing enabled to wake it
+    let mut system = make_test_system(&[(0x100, &[0xF3, 0x76])]); // di; halt
+    system.cpu.mmu.interrupt_enable = 0;
+    assert_eq!(system.run_instructions(100), 2);
+    assert_eq!(system.cpu.pc, Address(0x102));
+}
+
+#[test]
+fn test_stack_bounds() {
+    use crate::cpu::BreakReason;
+    use crate::mem::AddressRange;
+
+    let mut system = make_test_system(&[(0x100, &[0xC5; 8])]); // push bc
+    system
+        .debugger()
+        .set_stack_bounds(Some(AddressRange(Address(0xFFF8), Address(0xFFFF))));
+
+    let reasons = system.run_for_duration(&Duration::from_millis(1));
+
+    assert_eq!(reasons, vec![BreakReason::StackBounds(Address(0xFFF6))]);
+    assert_eq!(system.cpu.pc, Address(0x104));
+}
+
+#[test]
+fn test_profile_region() {
+    let mut system = make_test_system(&[
+        (0x100, &[0xC3, 0x50, 0x01]), // jp $0150
+        (0x150, &[0x06, 0x03]),       // ld b,3
+        (0x152, &[0x05, 0x20, 0xFD]), // dec b; jr nz,-3
+    ]);
+
+    assert_eq!(
+        system.profile_region(Address(0x152), Address(0x155), 1000),
+        Some(3 * 4 + 2 * 12 + 8)
+    );
+    assert_eq!(system.cpu.pc, Address(0x155));
+
+    let mut system = make_test_system(&[(0x100, &[0x18, 0xFE])]); // jr -2
+    assert_eq!(
+        system.profile_region(Address(0x100), Address(0x200), 1000),
+        None
+    );
+    assert!(system.cpu.cycle() >= 1000);
+
+    let mut system = make_test_system(&[(0x100, &[0xF3, 0x76])]); // di; halt
+    system.cpu.mmu.interrupt_enable = 0;
+    assert_eq!(
+        system.profile_region(Address(0x100), Address(0x200), 1000),
+        None
+    );
+}
+
+#[test]
+fn test_run_until_pc() {
+    let mut system = make_test_system(&[
+        (0x100, &[0xC3, 0x50, 0x01]), // jp $0150
+        (0x150, &[0x00, 0x18, 0xFE]), // nop; jr -2
+    ]);
+
+    assert!(system.run_until_pc(Address(0x151), 1000).unwrap());
+    assert_eq!(system.cpu.pc, Address(0x151));
+
+    assert!(!system.run_until_pc(Address(0x200), 1000).unwrap());
+    assert!(system.cpu.cycle() >= 1000);
+}
+
+#[test]
+fn test_set_program_counter() {
+    use crate::cpu::Register8;
+
+    let mut system = make_test_system(&[
+        (0x100, &[0x3E, 0x11]), // ld a, $11
+        (0x150, &[0x3E, 0x42]), // ld a, $42
+    ]);
+
+    system.set_program_counter(Address(0x150));
+    system.set_stack_pointer(Address(0xDFF0));
+    assert_eq!(system.program_counter(), Address(0x150));
+    assert_eq!(system.stack_pointer(), Address(0xDFF0));
+
+    system.run_instructions(1);
+    assert_eq!(system.cpu[Register8::A], 0x42);
+    assert_eq!(system.program_counter(), Address(0x152));
+    assert_eq!(system.stack_pointer(), Address(0xDFF0));
+}
+
+#[test]
+fn test_load_memory_image() {
+    use crate::cpu::Register8;
+
+    let mut system = make_test_system(&[(0x100, &[0xC3, 0x00, 0xC0])]); // jp $C000
+    system
+        .load_memory_image(&[
+            (Address(0xC000), 0x3E), // ld a, $41
+            (Address(0xC001), 0x41),
+            (Address(0xC002), 0x3C), // inc a
+            (Address(0xC003), 0x18), // jr -2
+            (Address(0xC004), 0xFE),
+        ])
+        .unwrap();
+
+    assert!(system.run_until_pc(Address(0xC003), 1000).unwrap());
+    assert_eq!(system.cpu[Register8::A], 0x42);
+}
+
+#[test]
+fn test_interrupt_log() {
+    use crate::cpu::Interrupt;
+
+    let mut system = make_test_system(&[]);
+    system.debugger().set_interrupt_log(true);
+    // Raise STAT on every OAM search
+    system
+        .debugger()
+        .write_mem(Address(0xFF41), 0b0010_0000)
+        .unwrap();
+
+    let start = system.cpu.cycle();
+    system.advance_frame();
+    let end = system.cpu.cycle();
+    let log = system.debugger().drain_interrupt_log();
+
+    assert!(log.iter().all(|e| e.cycle >= start && e.cycle <= end));
+    assert!(log.windows(2).all(|w| w[0].cycle <= w[1].cycle));
+    assert!(log
+        .iter()
+        .any(|e| e.interrupt == Interrupt::VBlank && e.ly == 144));
+    assert!(log
+        .iter()
+        .any(|e| e.interrupt == Interrupt::LCDC && e.ly < 144));
+    assert!(system.debugger().drain_interrupt_log().is_empty());
+}
+
+#[test]
+fn test_freeze_address() {
+    let mut system = make_test_system(&[
+        (0x100, &[0x3E, 0x42]),             // ld a, $42
+        (0x102, &[0xEA, 0x00, 0xC0]),       // ld ($C000), a
+        (0x105, &[0xEA, 0x01, 0xC0]),       // ld ($C001), a
+        (0x108, &[0xEA, 0x00, 0xC0, 0x00]), // ld ($C000), a; nop
+    ]);
+    system.freeze_address(Address(0xC000), 0x99).unwrap();
+    system.freeze_address(Address(0xC001), 0x98).unwrap();
+
+    assert!(system.run_until_pc(Address(0x108), 1000).unwrap());
+    assert_eq!(system.debugger().read_mem(Address(0xC000)).unwrap(), 0x99);
+    assert_eq!(system.debugger().read_mem(Address(0xC001)).unwrap(), 0x98);
+
+    system.unfreeze_address(Address(0xC000));
+    assert!(system.run_until_pc(Address(0x10B), 1000).unwrap());
+    assert_eq!(system.debugger().read_mem(Address(0xC000)).unwrap(), 0x42);
+    assert_eq!(system.debugger().read_mem(Address(0xC001)).unwrap(), 0x98);
+}
+
+#[test]
+fn test_reset_audio() {
+    let mut system = make_test_system(&[(0x100, &[0x18, 0xFE])]); // jr -2
+    system.advance_frame();
+    let mut debugger = system.debugger();
+    for &(reg, v) in &[
+        (0xFF26, 0x80),
+        (0xFF25, 0xFF),
+        (0xFF12, 0xF0),
+        (0xFF14, 0x80),
+        (0xFF17, 0xF0),
+        (0xFF19, 0x80),
+        (0xFF1A, 0x80),
+        (0xFF1E, 0x80),
+        (0xFF21, 0xF0),
+        (0xFF23, 0x80),
+    ] {
+        debugger.write_mem(Address(reg), v).unwrap();
+    }
+    assert_eq!(debugger.read_mem(Address(0xFF26)).unwrap() & 0x0F, 0x0F);
+    system.advance_frame();
+
+    system.reset_audio();
+    system.advance_frame();
+    system.advance_frame();
+    let debugger = system.debugger();
+    assert_eq!(debugger.read_mem(Address(0xFF26)).unwrap(), 0x80);
+    assert_eq!(debugger.read_mem(Address(0xFF25)).unwrap(), 0xFF);
+    assert_eq!(debugger.read_mem(Address(0xFF12)).unwrap(), 0x00);
+
+    // The length enable bits are back at their power-on values too
+    for &reg in &[0xFF14, 0xFF19, 0xFF1E, 0xFF23] {
+        assert_eq!(debugger.read_mem(Address(reg)).unwrap(), 0x00);
+    }
+
+    // Without a trigger, setting a channel up again doesn't make it active
+    let mut debugger = system.debugger();
+    debugger.write_mem(Address(0xFF12), 0xF0).unwrap();
+    debugger.write_mem(Address(0xFF14), 0x00).unwrap();
+    assert_eq!(debugger.read_mem(Address(0xFF26)).unwrap(), 0x80);
+    debugger.write_mem(Address(0xFF14), 0x80).unwrap();
+    assert_eq!(debugger.read_mem(Address(0xFF26)).unwrap(), 0x81);
+}
+
+#[test]
+fn test_timer_interrupt_handler() {
+    let mut system = make_test_system(&[
+        (0x50, &[0x04, 0xD9]), // inc b; reti
+        (
+            0x100,
+            &[
+                0x3E, 0x05, 0xE0, 0x07, // tac = 16 cycles per increment
+                0x3E, 0xFE, 0xE0, 0x06, // tma = $FE
+                0x3E, 0x04, 0xE0, 0xFF, // ie = timer
+                0xFB, 0x18, 0xFE, // ei; jr -2
+            ],
+        ),
+    ]);
+
+    // The handler's first instruction runs in the same step the interrupt is taken
+    assert!(system.run_until_pc(Address(0x51), 10_000).unwrap());
+    let first = system.cpu.cycle();
+    assert!(first >= 256 * 16);
+
+    // After that TIMA reloads from TMA, so it overflows every other increment
+    assert!(system.run_until_pc(Address(0x10D), 1_000).unwrap());
+    assert!(system.run_until_pc(Address(0x51), 1_000).unwrap());
+    assert!(system.cpu.cycle() - first <= 2 * 16 + 32);
+    assert_eq!(system.cpu[crate::cpu::Register8::B], 0x02);
+}
+
+#[test]
+fn test_turbo() {
+    let program: &[(usize, &[u8])] = &[(0x100, &[0x18, 0xFE])]; // jr -2
+    let duration = Duration::from_millis(5);
+
+    let mut system = make_test_system(program);
+    system.run_for_duration(&duration);
+    let normal = system.cpu.cycle();
+
+    let mut system = make_test_system(program);
+    system.set_turbo(true);
+    assert!(system.is_turbo());
+    system.run_for_duration(&duration);
+    let turbo = system.cpu.cycle();
+    assert!(turbo >= normal * 3, "{} vs {}", turbo, normal);
+
+    system.set_turbo(false);
+    system.run_for_duration(&duration);
+    assert!(system.cpu.cycle() - turbo < normal * 2);
+
+    system.set_turbo_speed(Some(8));
+    system.set_turbo(true);
+    let before = system.cpu.cycle();
+    system.run_for_duration(&duration);
+    assert!(system.cpu.cycle() - before >= normal * 7);
+
+    // Uncapped always emulates at least a frame, however short the duration
+    system.set_turbo_speed(None);
+    assert_eq!(system.turbo_speed(), None);
+    let before = system.cpu.cycle();
+    system.run_for_duration(&Duration::from_secs(0));
+    assert!(system.cpu.cycle() - before >= crate::lcd::SCREEN_CYCLE_TIME);
+}
+
+#[test]
+fn test_turbo_governor() {
+    let mut system = make_test_system(&[(0x100, &[0x18, 0xFE])]); // jr -2
+    let target = system.governor().target();
+    assert_eq!(target, Governor::default().target());
+
+    system.set_turbo(true);
+    assert_eq!(system.governor().target(), target / DEFAULT_TURBO_SPEED);
+    system.set_turbo_speed(Some(2));
+    assert_eq!(system.governor().target(), target / 2);
+
+    // A frame that took as long as a normal one is too slow, so it's skipped to catch up
+    let mut governor = system.governor();
+    assert_eq!(governor.frame_finished(target), GovernorAction::SkipFrame);
+
+    system.set_turbo_speed(None);
+    let mut governor = system.governor();
+    let frame = system.cpu.mmu.lcd.frame_count();
+    system.advance_frame();
+    assert_eq!(system.cpu.mmu.lcd.frame_count(), frame + 1);
+    assert_eq!(governor.frame_finished(target), GovernorAction::Continue);
+    assert_eq!(governor.frame_finished(target), GovernorAction::Continue);
+}
+
+#[test]
+fn test_div_follows_cycle_count() {
+    let mut system = make_test_system(&[]); // nops all the way
+    system.run_instructions(640);
+    let cycle = system.cpu.cycle();
+    assert_eq!(cycle, 640 * 4);
+    assert_eq!(
+        system.debugger().read_mem(Address(0xFF04)).unwrap(),
+        (cycle / 256) as u8
+    );
+
+    system.debugger().write_mem(Address(0xFF04), 0x55).unwrap();
+    assert_eq!(system.debugger().read_mem(Address(0xFF04)).unwrap(), 0);
+    system.run_instructions(64);
+    assert_eq!(system.debugger().read_mem(Address(0xFF04)).unwrap(), 1);
+}
+
+#[test]
+fn test_square_channel_debug() {
+    let mut system = make_test_system(&[]);
+    let debugger = system.debugger();
+    assert_eq!(debugger.square_phase(1), Some(0));
+    assert!(debugger.square_duty_pattern(2).is_some());
+    assert_eq!(debugger.square_phase(3), None);
+    assert_eq!(debugger.square_duty_pattern(0), None);
+}
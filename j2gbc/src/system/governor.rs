@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use crate::{cpu::CLOCK_RATE, lcd::SCREEN_CYCLE_TIME};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GovernorAction {
+    Continue,
+    Sleep(Duration),
+    SkipFrame,
+}
+
+pub struct Governor {
+    target: Duration,
+    lag: Duration,
+}
+
+impl Governor {
+    pub fn new(target: Duration) -> Governor {
+        Governor {
+            target,
+            lag: Duration::from_secs(0),
+        }
+    }
+
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+
+    // Given how long the last frame took, decide whether the front-end should sleep off the
+    // remaining time, or skip presenting the next frame to catch back up.
+    // A zero target is uncapped: every frame is shown as soon as it's ready.
+    pub fn frame_finished(&mut self, elapsed: Duration) -> GovernorAction {
+        if self.target == Duration::from_secs(0) {
+            return GovernorAction::Continue;
+        }
+
+        if elapsed < self.target {
+            let spare = self.target - elapsed;
+            if self.lag >= spare {
+                self.lag -= spare;
+                GovernorAction::Continue
+            } else {
+                let sleep = spare - self.lag;
+                self.lag = Duration::from_secs(0);
+                GovernorAction::Sleep(sleep)
+            }
+        } else {
+            self.lag += elapsed - self.target;
+            if self.lag >= self.target {
+                self.lag -= self.target;
+                GovernorAction::SkipFrame
+            } else {
+                GovernorAction::Continue
+            }
+        }
+    }
+}
+
+impl Default for Governor {
+    fn default() -> Governor {
+        let nanos = SCREEN_CYCLE_TIME * 1_000_000_000 / CLOCK_RATE;
+        Governor::new(Duration::from_nanos(nanos))
+    }
+}
+
+#[test]
+fn test_governor_decisions() {
+    let ms = Duration::from_millis;
+    let mut governor = Governor::new(ms(16));
+
+    assert_eq!(
+        governor.frame_finished(ms(10)),
+        GovernorAction::Sleep(ms(6))
+    );
+    assert_eq!(governor.frame_finished(ms(16)), GovernorAction::Continue);
+
+    // Running 10ms behind isn't enough to skip, but it eats into the next sleep
+    assert_eq!(governor.frame_finished(ms(26)), GovernorAction::Continue);
+    assert_eq!(governor.frame_finished(ms(8)), GovernorAction::Continue);
+    assert_eq!(governor.frame_finished(ms(8)), GovernorAction::Sleep(ms(6)));
+
+    // A long stall should skip frames until caught up
+    assert_eq!(governor.frame_finished(ms(48)), GovernorAction::SkipFrame);
+    assert_eq!(governor.frame_finished(ms(16)), GovernorAction::SkipFrame);
+    assert_eq!(governor.frame_finished(ms(16)), GovernorAction::Continue);
+    assert_eq!(
+        governor.frame_finished(ms(12)),
+        GovernorAction::Sleep(ms(4))
+    );
+}
@@ -0,0 +1,161 @@
+use std::io::{self, BufRead};
+
+use super::System;
+use crate::{
+    cpu::{Cpu, Register8},
+    error::ExecutionError,
+    inst::Instruction,
+    mem::Address,
+};
+
+// CPU state before executing a single step, in the "A:01 F:B0 ... SP:FFFE PC:0100" format
+// used by other emulators' trace logs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceState {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: Address,
+    pub pc: Address,
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub step: usize,
+    pub instruction: Option<Instruction>,
+    pub expected: TraceState,
+    pub actual: TraceState,
+}
+
+impl TraceState {
+    fn from_cpu(cpu: &Cpu) -> TraceState {
+        TraceState {
+            a: cpu[Register8::A],
+            f: cpu[Register8::F],
+            b: cpu[Register8::B],
+            c: cpu[Register8::C],
+            d: cpu[Register8::D],
+            e: cpu[Register8::E],
+            h: cpu[Register8::H],
+            l: cpu[Register8::L],
+            sp: cpu.sp,
+            pc: cpu.pc,
+        }
+    }
+
+    pub fn parse(line: &str) -> io::Result<TraceState> {
+        let mut state = TraceState {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            sp: Address(0),
+            pc: Address(0),
+        };
+        let mut seen = 0;
+
+        for field in line.split_whitespace() {
+            let mut parts = field.splitn(2, ':');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) => (k, v),
+                _ => continue,
+            };
+
+            let parse = |v: &str| {
+                u16::from_str_radix(v, 16)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            };
+
+            match key {
+                "A" => state.a = parse(value)? as u8,
+                "F" => state.f = parse(value)? as u8,
+                "B" => state.b = parse(value)? as u8,
+                "C" => state.c = parse(value)? as u8,
+                "D" => state.d = parse(value)? as u8,
+                "E" => state.e = parse(value)? as u8,
+                "H" => state.h = parse(value)? as u8,
+                "L" => state.l = parse(value)? as u8,
+                "SP" => state.sp = Address(parse(value)?),
+                "PC" => state.pc = Address(parse(value)?),
+                _ => continue,
+            }
+            seen += 1;
+        }
+
+        if seen != 10 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Incomplete trace line: {}", line),
+            ));
+        }
+
+        Ok(state)
+    }
+}
+
+pub fn load_trace<R: BufRead>(r: R) -> io::Result<Vec<TraceState>> {
+    let mut trace = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        trace.push(TraceState::parse(&line)?);
+    }
+    Ok(trace)
+}
+
+// Steps the CPU one instruction per trace entry, stopping at the first state that differs
+pub fn replay(
+    system: &mut System,
+    trace: &[TraceState],
+) -> Result<Option<Divergence>, ExecutionError> {
+    for (step, expected) in trace.iter().enumerate() {
+        let actual = TraceState::from_cpu(&system.cpu);
+        if actual != *expected {
+            let instruction = system.cpu.fetch_instruction(actual.pc).ok().map(|(i, _)| i);
+            return Ok(Some(Divergence {
+                step,
+                instruction,
+                expected: *expected,
+                actual,
+            }));
+        }
+
+        system.cpu.run_cycle()?;
+    }
+
+    Ok(None)
+}
+
+#[test]
+fn test_replay_trace() {
+    let trace = "\
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,06,07
+A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:06,07,00,00
+A:42 F:B0 B:07 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0104 PCMEM:00,00,00,00
+";
+    let trace = load_trace(trace.as_bytes()).unwrap();
+    let rom = &[(0x100, &[0x3E, 0x42, 0x06, 0x07][..])]; // ld a,$42; ld b,$07
+
+    let mut system = super::make_test_system(rom);
+    assert!(replay(&mut system, &trace).unwrap().is_none());
+
+    let mut diverged = trace.clone();
+    diverged[2].b = 0x08;
+    let mut system = super::make_test_system(rom);
+    let divergence = replay(&mut system, &diverged).unwrap().unwrap();
+    assert_eq!(divergence.step, 2);
+    assert_eq!(divergence.actual.b, 0x07);
+    assert_eq!(divergence.expected.b, 0x08);
+    assert_eq!(divergence.instruction, Some(Instruction::Nop));
+}
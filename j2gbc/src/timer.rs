@@ -1,12 +1,12 @@
 use std::cmp::min;
-use std::num::Wrapping;
 
 use super::cpu::{Interrupt, InterruptSet, CLOCK_RATE};
 use super::mem::*;
 use crate::error::ExecutionError;
 
-const DIV_INCREMENT_CYCLE_COUNT: u64 = CLOCK_RATE / 16_779;
-const TIMA_INCREMENT_CYCLE_COUNT: [u64; 4] = [
+// DIV is the upper byte of a 16 bit counter that ticks once per cycle (twice in double speed)
+const DIV_INCREMENT_TICK_COUNT: u64 = 256;
+const TIMA_INCREMENT_TICK_COUNT: [u64; 4] = [
     CLOCK_RATE / 4_096,
     CLOCK_RATE / 262_144,
     CLOCK_RATE / 65_536,
@@ -15,29 +15,28 @@ const TIMA_INCREMENT_CYCLE_COUNT: [u64; 4] = [
 
 #[derive(Default)]
 pub struct Timer {
-    div: u8,
+    // Ticks of the internal counter since it was last reset through DIV
+    ticks: u64,
     tima: u8,
     tma: u8,
     tac: u8,
 
     double_speed: bool,
 
-    next_div_cycle: u64,
-    next_tima_cycle: u64,
+    last_cycle: u64,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            div: 0,
+            ticks: 0,
             tima: 0,
             tma: 0,
             tac: 0,
 
             double_speed: false,
 
-            next_div_cycle: 0,
-            next_tima_cycle: 0,
+            last_cycle: 0,
         }
     }
 
@@ -50,56 +49,73 @@ impl Timer {
     }
 
     fn tima_duration(&self) -> u64 {
-        TIMA_INCREMENT_CYCLE_COUNT[(self.tac & 0b11) as usize]
+        TIMA_INCREMENT_TICK_COUNT[(self.tac & 0b11) as usize]
+    }
+
+    fn ticks_per_cycle(&self) -> u64 {
+        if self.double_speed {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn div(&self) -> u8 {
+        (self.ticks / DIV_INCREMENT_TICK_COUNT) as u8
+    }
+
+    // First cycle at which the counter reaches the next multiple of `period`
+    fn next_multiple_cycle(&self, period: u64) -> u64 {
+        let remaining = period - self.ticks % period;
+        self.last_cycle + remaining.div_ceil(self.ticks_per_cycle())
     }
 
     pub fn get_next_event_cycle(&self) -> u64 {
+        let next_div_cycle = self.next_multiple_cycle(DIV_INCREMENT_TICK_COUNT);
         if self.tima_enabled() {
-            min(self.next_div_cycle, self.next_tima_cycle)
+            min(
+                next_div_cycle,
+                self.next_multiple_cycle(self.tima_duration()),
+            )
         } else {
-            self.next_div_cycle
+            next_div_cycle
         }
     }
 
     pub fn pump_cycle(&mut self, cycle: u64) -> InterruptSet {
-        if self.next_div_cycle <= cycle {
-            self.next_div_cycle =
-                cycle + maybe_half_cycle(DIV_INCREMENT_CYCLE_COUNT, self.double_speed);
-            self.div = (Wrapping(self.div) + Wrapping(1)).0;
+        let mut inters = InterruptSet::default();
+        if cycle <= self.last_cycle {
+            return inters;
         }
 
-        if self.tima_enabled() && self.next_tima_cycle <= cycle {
-            self.next_tima_cycle =
-                cycle + maybe_half_cycle(self.tima_duration(), self.double_speed);
+        let old_ticks = self.ticks;
+        self.ticks += (cycle - self.last_cycle) * self.ticks_per_cycle();
+        self.last_cycle = cycle;
 
-            if self.tima == 0xFF {
-                self.tima = self.tma;
-                Interrupt::Timer.into()
-            } else {
-                self.tima += 1;
-                InterruptSet::default()
+        if self.tima_enabled() {
+            let period = self.tima_duration();
+            for _ in old_ticks / period..self.ticks / period {
+                if self.tima == 0xFF {
+                    self.tima = self.tma;
+                    inters.add_interrupt(Interrupt::Timer);
+                } else {
+                    self.tima += 1;
+                }
             }
-        } else {
-            InterruptSet::default()
         }
-    }
-}
 
-fn maybe_half_cycle(cycle_count: u64, double_speed: bool) -> u64 {
-    if double_speed {
-        cycle_count >> 2
-    } else {
-        cycle_count
+        inters
     }
 }
 
 impl MemDevice for Timer {
     fn read(&self, a: Address) -> Result<u8, ExecutionError> {
         match a {
-            REG_DIV => Ok(self.div),
+            REG_DIV => Ok(self.div()),
             REG_TIMA => Ok(self.tima),
             REG_TMA => Ok(self.tma),
-            REG_TAC => Ok(self.tac),
+            // Only the low three bits of TAC exist, the rest read back as 1
+            REG_TAC => Ok(self.tac | 0b1111_1000),
             _ => unreachable!(),
         }
     }
@@ -107,7 +123,7 @@ impl MemDevice for Timer {
     fn write(&mut self, a: Address, v: u8) -> Result<(), ExecutionError> {
         match a {
             REG_DIV => {
-                self.div = 0;
+                self.ticks = 0;
             }
             REG_TIMA => {
                 self.tima = v;
@@ -116,7 +132,7 @@ impl MemDevice for Timer {
                 self.tma = v;
             }
             REG_TAC => {
-                self.tac = v;
+                self.tac = v & 0b111;
             }
             _ => unreachable!(),
         }
@@ -124,3 +140,90 @@ impl MemDevice for Timer {
         Ok(())
     }
 }
+
+#[test]
+fn test_div_reset() {
+    let mut timer = Timer::new();
+    timer.pump_cycle(256 * 5 + 10);
+    assert_eq!(timer.read(REG_DIV).unwrap(), 5);
+
+    timer.write(REG_DIV, 0x12).unwrap();
+    assert_eq!(timer.read(REG_DIV).unwrap(), 0);
+    assert_eq!(timer.get_next_event_cycle(), 256 * 6 + 10);
+
+    timer.pump_cycle(256 * 6 + 9);
+    assert_eq!(timer.read(REG_DIV).unwrap(), 0);
+    timer.pump_cycle(256 * 6 + 10);
+    assert_eq!(timer.read(REG_DIV).unwrap(), 1);
+}
+
+#[test]
+fn test_div_reset_restarts_tima() {
+    let mut timer = Timer::new();
+    timer.write(REG_TAC, 0b101).unwrap(); // 16 cycles per increment
+    timer.pump_cycle(24);
+    assert_eq!(timer.read(REG_TIMA).unwrap(), 1);
+
+    timer.write(REG_DIV, 0).unwrap();
+    timer.pump_cycle(39);
+    assert_eq!(timer.read(REG_TIMA).unwrap(), 1);
+    timer.pump_cycle(40);
+    assert_eq!(timer.read(REG_TIMA).unwrap(), 2);
+}
+
+#[test]
+fn test_tac_unused_bits() {
+    let mut timer = Timer::new();
+    timer.write(REG_TAC, 0x00).unwrap();
+    assert_eq!(timer.read(REG_TAC).unwrap(), 0xF8);
+    assert!(!timer.tima_enabled());
+    assert_eq!(timer.tima_duration(), 1024);
+
+    timer.write(REG_TAC, 0xFD).unwrap();
+    assert_eq!(timer.read(REG_TAC).unwrap(), 0xFD);
+    assert!(timer.tima_enabled());
+
+    for &(select, duration) in &[(0b00, 1024), (0b01, 16), (0b10, 64), (0b11, 256)] {
+        timer.write(REG_TAC, 0xF0 | select).unwrap();
+        assert_eq!(timer.read(REG_TAC).unwrap(), 0xF8 | select);
+        assert_eq!(timer.tima_duration(), duration);
+    }
+}
+
+#[test]
+fn test_tima_overflow_for_each_frequency() {
+    for &(select, hz) in &[
+        (0b00, 4_096),
+        (0b01, 262_144),
+        (0b10, 65_536),
+        (0b11, 16_384),
+    ] {
+        let period = CLOCK_RATE / hz;
+        let mut timer = Timer::new();
+        timer.write(REG_TMA, 0xF0).unwrap();
+        timer.write(REG_TAC, 0b100 | select).unwrap();
+
+        let overflow = 256 * period;
+        assert_eq!(timer.pump_cycle(overflow - 1).if_(), 0);
+        assert_eq!(timer.read(REG_TIMA).unwrap(), 0xFF);
+        assert_eq!(
+            timer.pump_cycle(overflow).if_(),
+            Interrupt::Timer.bits(),
+            "{} Hz",
+            hz
+        );
+        assert_eq!(timer.read(REG_TIMA).unwrap(), 0xF0);
+
+        // Reloaded from TMA, so the next overflow comes 16 increments later
+        assert_eq!(timer.pump_cycle(overflow + 16 * period - 1).if_(), 0);
+        assert_eq!(
+            timer.pump_cycle(overflow + 16 * period).if_(),
+            Interrupt::Timer.bits()
+        );
+    }
+
+    let mut timer = Timer::new();
+    timer.write(REG_TAC, 0b001).unwrap();
+    assert_eq!(timer.pump_cycle(1_000_000).if_(), 0);
+    assert_eq!(timer.read(REG_TIMA).unwrap(), 0);
+}